@@ -0,0 +1,122 @@
+use std::{
+    fs::{DirBuilder, File, OpenOptions},
+    io,
+    os::unix::{fs::DirBuilderExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use jj_lib::settings::UserSettings;
+use proto::{health::health_check_response::ServingStatus, Address};
+use tracing::info;
+
+use crate::client::ClientPool;
+
+/// How long a freshly spawned daemon has to start accepting connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Starts a local daemon for `address` unless one is already serving it.
+/// Remote (TCP) daemons are never started, and users can opt out with
+/// `cultivate.auto-start = false`.
+pub fn ensure_running(settings: &UserSettings, address: &Address) -> io::Result<()> {
+    if let Address::Tcp(_) = address {
+        return Ok(());
+    }
+    let auto_start = settings
+        .config()
+        .get_bool("cultivate.auto-start")
+        .unwrap_or(true);
+    if !auto_start || is_ready(&probe(address)?) {
+        return Ok(());
+    }
+    start(settings, address)
+}
+
+/// Spawns a detached daemon serving `address` and waits until it accepts
+/// connections.
+///
+/// Concurrent `jj` invocations serialize on a lock file next to the socket,
+/// so only the first one spawns a daemon and the rest find it running.
+pub fn start(settings: &UserSettings, address: &Address) -> io::Result<()> {
+    let Address::Unix(socket) = address else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Only local daemons can be started, not {address}"),
+        ));
+    };
+    let run_dir = socket.parent().unwrap_or(Path::new("."));
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(run_dir)?;
+
+    let lock = File::create(run_dir.join("daemon.lock"))?;
+    lock.lock()?;
+    let daemon = probe(address)?;
+    if is_ready(&daemon) {
+        return Ok(());
+    }
+
+    let daemon_path = daemon_path(settings)?;
+    info!("Starting {} on {address}", daemon_path.display());
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(run_dir.join("daemon.log"))?;
+    let mut child = Command::new(&daemon_path)
+        .env(proto::ADDRESS_ENV, address.to_string())
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // Outlive this jj invocation, and don't receive its terminal's signals
+        .process_group(0)
+        .spawn()
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Failed to start {}: {err}", daemon_path.display()),
+            )
+        })?;
+
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !is_ready(&daemon) {
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!(
+                "cultivate daemon exited during startup ({status}), see {}",
+                run_dir.join("daemon.log").display()
+            )));
+        }
+        if Instant::now() > deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("cultivate daemon did not start listening on {address}"),
+            ));
+        }
+        std::thread::sleep(STARTUP_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Whether the daemon is up and reports itself ready through the gRPC health
+/// service. A daemon still starting up, or already shutting down, is not.
+pub fn is_ready(daemon: &ClientPool) -> bool {
+    daemon
+        .health("")
+        .is_ok_and(|status| status == ServingStatus::Serving)
+}
+
+fn probe(address: &Address) -> io::Result<ClientPool> {
+    ClientPool::connect_once(address).map_err(io::Error::other)
+}
+
+/// The `cultivate.daemon-path` setting, or the daemon installed alongside
+/// this binary.
+fn daemon_path(settings: &UserSettings) -> io::Result<PathBuf> {
+    if let Ok(path) = settings.config().get_string("cultivate.daemon-path") {
+        return Ok(PathBuf::from(path));
+    }
+    let exe = std::env::current_exe()?;
+    Ok(exe.with_file_name("daemon"))
+}
@@ -1,17 +1,21 @@
 use std::{
     any::Any,
-    io::{Cursor, Read},
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Cursor, Read},
     path::Path,
-    time::SystemTime,
+    sync::Mutex,
+    time::{Duration, SystemTime},
 };
 
 use async_trait::async_trait;
 use futures::stream::BoxStream;
 use jj_lib::{
     backend::{
-        make_root_commit, Backend, BackendError, BackendInitError, BackendResult, ChangeId, Commit,
-        CommitId, Conflict, ConflictId, CopyRecord, FileId, MergedTreeId, MillisSinceEpoch,
-        SecureSig, Signature, SigningFn, SymlinkId, Timestamp, Tree, TreeId, TreeValue,
+        make_root_commit, Backend, BackendError, BackendInitError, BackendLoadError, BackendResult,
+        ChangeId, Commit, CommitId, Conflict, ConflictId, CopyRecord, FileId, MergedTreeId,
+        MillisSinceEpoch, SecureSig, Signature, SigningFn, SymlinkId, Timestamp, Tree, TreeId,
+        TreeValue,
     },
     index::Index,
     merge::MergeBuilder,
@@ -21,17 +25,50 @@ use jj_lib::{
 };
 use prost::Message;
 
-use crate::blocking_client::BlockingJujutsuInterfaceClient;
+use crate::{
+    autostart,
+    blocking_client::BlockingJujutsuInterfaceClient,
+    client::{daemon_address, ClientPool, RetryPolicy},
+};
 
 const COMMIT_ID_LENGTH: usize = 32;
 const CHANGE_ID_LENGTH: usize = 16;
+/// Upper bound on trees held by the prefetch cache. Once it is full, reads
+/// are not prefetched until jj has consumed some of it.
+const PREFETCH_CACHE_SIZE: usize = 4096;
+/// Upper bound on the trees fetched ahead of a single `read_tree`.
+const PREFETCH_TREES: usize = 256;
+/// Buffered file content is sent once it grows past this, well below the
+/// transport's 4 MiB message limit.
+const PENDING_FILES_BYTES: usize = 1024 * 1024;
+/// Connections to the daemon. Also caps the concurrency the daemon reports.
+const CONNECTION_POOL_SIZE: usize = 8;
+/// File in the store directory holding the id the daemon knows the repo by.
+const REPO_ID_FILE: &str = "repo_id";
 
 #[derive(Debug)]
 pub struct CultivateBackend {
+    /// Used by the async `Backend` methods.
+    pool: ClientPool,
     client: BlockingJujutsuInterfaceClient,
     root_commit_id: CommitId,
     root_change_id: ChangeId,
     empty_tree_id: TreeId,
+    /// Parallel reads the daemon says it can serve.
+    concurrency: usize,
+    /// Descendants of trees read from the daemon, fetched ahead with
+    /// `ReadTrees` and consumed on first read.
+    prefetched_trees: Mutex<HashMap<TreeId, Tree>>,
+    /// Files written since the last `WriteFiles`.
+    pending_files: Mutex<PendingFiles>,
+}
+
+/// Files `write_file` has handed out ids for but not sent yet. Trees may only
+/// reference files the daemon has, so they are sent before any tree is.
+#[derive(Debug, Default)]
+struct PendingFiles {
+    files: HashMap<FileId, model::File>,
+    bytes: usize,
 }
 
 impl CultivateBackend {
@@ -39,20 +76,196 @@ impl CultivateBackend {
         "cultivate"
     }
 
-    pub fn new(_settings: &UserSettings, _store_path: &Path) -> Result<Self, BackendInitError> {
+    /// Sets up the store of a repo the daemon just created as `repo_id`.
+    pub fn init(
+        settings: &UserSettings,
+        store_path: &Path,
+        repo_id: &str,
+    ) -> Result<Self, BackendInitError> {
+        fs::write(store_path.join(REPO_ID_FILE), repo_id)
+            .map_err(|err| BackendInitError(err.into()))?;
+        Self::new(settings, store_path)
+    }
+
+    /// Loads the backend of an existing repo, failing rather than panicking
+    /// when the daemon can't be reached.
+    pub fn load(settings: &UserSettings, store_path: &Path) -> Result<Self, BackendLoadError> {
+        Self::new(settings, store_path).map_err(|BackendInitError(err)| BackendLoadError(err))
+    }
+
+    pub fn new(settings: &UserSettings, store_path: &Path) -> Result<Self, BackendInitError> {
         let root_commit_id = CommitId::from_bytes(&[0; COMMIT_ID_LENGTH]);
         let root_change_id = ChangeId::from_bytes(&[0; CHANGE_ID_LENGTH]);
-        let client = BlockingJujutsuInterfaceClient::connect("http://[::1]:10000").unwrap();
-        let empty_tree_id =
-            TreeId::from_bytes(&client.get_empty_tree_id().unwrap().into_inner().tree_id);
+        let mut policy = RetryPolicy::default();
+        if let Ok(timeout) = settings.config().get::<u64>("cultivate.timeout") {
+            policy.timeout = Duration::from_secs(timeout);
+        }
+        let address = daemon_address(settings);
+        autostart::ensure_running(settings, &address)
+            .map_err(|err| BackendInitError(err.into()))?;
+        let mut pool = ClientPool::connect(&address, CONNECTION_POOL_SIZE, policy)
+            .map_err(|err| BackendInitError(err.into()))?;
+        if let Some(repo_id) =
+            read_repo_id(store_path).map_err(|err| BackendInitError(err.into()))?
+        {
+            pool = pool
+                .with_repo_id(&repo_id)
+                .map_err(|err| BackendInitError(err.into()))?;
+        }
+        let client = BlockingJujutsuInterfaceClient::new(pool.clone());
+        let empty_tree_id = client
+            .get_empty_tree_id()
+            .map_err(|err| BackendInitError(err.into()))?
+            .into_inner()
+            .tree_id;
+        let empty_tree_id = TreeId::new(empty_tree_id);
+        // Daemons that can't tell us are only trusted with serial reads
+        let concurrency = client.concurrency().map_or(1, |reply| {
+            let concurrency = reply.into_inner().estimated_concurrency;
+            usize::try_from(concurrency).map_or(1, |c| c.clamp(1, pool.size()))
+        });
 
         Ok(CultivateBackend {
+            pool,
             client,
             root_commit_id,
             root_change_id,
             empty_tree_id,
+            concurrency,
+            prefetched_trees: Mutex::new(HashMap::new()),
+            pending_files: Mutex::new(PendingFiles::default()),
         })
     }
+
+    /// The working copy shares the backend's connections to the daemon.
+    pub fn client(&self) -> &BlockingJujutsuInterfaceClient {
+        &self.client
+    }
+
+    /// The pending files the daemon lacks, asked for in one `FindMissing`.
+    /// Ids are computed over the uncompressed content, so files the daemon
+    /// already has never need to be sent. If the daemon can't say, all of
+    /// them are sent.
+    fn missing_files<'a>(&self, pending: &'a PendingFiles) -> Vec<&'a FileId> {
+        let ids = pending
+            .files
+            .keys()
+            .map(|id| proto::jj_interface::TypedId {
+                kind: proto::jj_interface::ObjectKind::File.into(),
+                id: id.to_bytes(),
+            })
+            .collect();
+        let req = proto::jj_interface::FindMissingReq { ids };
+        let Ok(reply) = self.client.find_missing(req) else {
+            return pending.files.keys().collect();
+        };
+        let missing: HashSet<_> = reply
+            .into_inner()
+            .missing
+            .into_iter()
+            .map(|typed_id| typed_id.id)
+            .collect();
+        pending
+            .files
+            .keys()
+            .filter(|id| missing.contains(id.as_bytes()))
+            .collect()
+    }
+
+    /// Sends the pending files the daemon lacks in one `WriteFiles`. They
+    /// stay pending if that fails, so the error is reported again by the next
+    /// flush.
+    fn flush_files(&self, pending: &mut PendingFiles) -> BackendResult<()> {
+        if pending.files.is_empty() {
+            return Ok(());
+        }
+        let missing = self.missing_files(pending);
+        if missing.is_empty() {
+            *pending = PendingFiles::default();
+            return Ok(());
+        }
+        let mut files = Vec::with_capacity(missing.len());
+        let mut expected_ids = Vec::with_capacity(missing.len());
+        for id in missing {
+            let file = &pending.files[id];
+            files.push(file.as_proto().map_err(|err| BackendError::WriteObject {
+                object_type: "file",
+                source: err.into(),
+            })?);
+            expected_ids.push(file_id_to_proto(id));
+        }
+        let req = proto::jj_interface::WriteFilesReq {
+            files,
+            expected_ids,
+        };
+        self.client
+            .write_files(req)
+            .map_err(|status| write_error("file", status))?;
+        *pending = PendingFiles::default();
+        Ok(())
+    }
+
+    fn take_prefetched_tree(&self, id: &TreeId) -> Option<Tree> {
+        self.prefetched_trees.lock().unwrap().remove(id)
+    }
+
+    /// Fetches the descendants of `tree` before jj asks for them, with one
+    /// `ReadTrees` per level and at most `PREFETCH_TREES` trees in total. jj
+    /// walks trees one level at a time (e.g. when diffing), so such a walk
+    /// costs a round trip per level rather than per tree. This is
+    /// best-effort, failures are left for the actual read to report.
+    async fn prefetch_descendants(&self, tree: &Tree) {
+        let (mut level, mut budget) = {
+            let prefetched = self.prefetched_trees.lock().unwrap();
+            let budget = PREFETCH_CACHE_SIZE
+                .saturating_sub(prefetched.len())
+                .min(PREFETCH_TREES);
+            let mut level = vec![];
+            push_uncached_subtrees(tree, &prefetched, &mut level);
+            (level, budget)
+        };
+        while !level.is_empty() && budget > 0 {
+            level.truncate(budget);
+            budget -= level.len();
+            let req = proto::jj_interface::ReadTreesReq {
+                tree_ids: level.iter().map(tree_id_to_proto).collect(),
+            };
+            let reply = self
+                .pool
+                .call(move |mut client| {
+                    let req = req.clone();
+                    async move { client.read_trees(req).await }
+                })
+                .await;
+            let Ok(reply) = reply else {
+                return;
+            };
+
+            let mut prefetched = self.prefetched_trees.lock().unwrap();
+            let mut next = vec![];
+            for (id, proto) in level.into_iter().zip(reply.into_inner().trees) {
+                let Ok(tree) = model::Tree::try_from(proto) else {
+                    return;
+                };
+                let tree = tree_from_model(tree);
+                push_uncached_subtrees(&tree, &prefetched, &mut next);
+                prefetched.insert(id, tree);
+            }
+            level = next;
+        }
+    }
+}
+
+/// Adds the ids of `tree`'s subtrees that are neither in `prefetched` nor
+/// already in `ids` to `ids`.
+fn push_uncached_subtrees(tree: &Tree, prefetched: &HashMap<TreeId, Tree>, ids: &mut Vec<TreeId>) {
+    for entry in tree.entries() {
+        if let TreeValue::Tree(id) = entry.value() {
+            if !prefetched.contains_key(id) && !ids.contains(id) {
+                ids.push(id.clone());
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -86,37 +299,82 @@ impl Backend for CultivateBackend {
     }
 
     fn concurrency(&self) -> usize {
-        1
+        self.concurrency
     }
 
-    async fn read_file(&self, _path: &RepoPath, id: &FileId) -> BackendResult<Box<dyn Read>> {
+    async fn read_file(&self, path: &RepoPath, id: &FileId) -> BackendResult<Box<dyn Read>> {
+        if let Some(file) = self.pending_files.lock().unwrap().files.get(id) {
+            return Ok(Box::new(Cursor::new(file.content.clone())));
+        }
+        let req = file_id_to_proto(id);
         let proto = self
-            .client
-            .read_file(file_id_to_proto(id))
-            .unwrap()
+            .pool
+            .call(move |mut client| {
+                let req = req.clone();
+                async move { client.read_file(req).await }
+            })
+            .await
+            .map_err(|status| read_error("file", id, status))?
             .into_inner();
-        Ok(file_from_proto(proto))
+        let file = model::File::try_from(proto).map_err(|err| BackendError::ReadFile {
+            path: path.to_owned(),
+            id: id.clone(),
+            source: err.into(),
+        })?;
+        Ok(Box::new(Cursor::new(file.content)))
     }
 
     fn write_file(&self, _path: &RepoPath, contents: &mut dyn Read) -> BackendResult<FileId> {
-        let proto = file_to_proto(contents);
-        let id = self.client.write_file(proto).unwrap();
-        let id = id.into_inner();
-        Ok(FileId::new(id.file_id))
+        let mut content = vec![];
+        contents
+            .read_to_end(&mut content)
+            .map_err(|err| BackendError::WriteObject {
+                object_type: "file",
+                source: err.into(),
+            })?;
+
+        let file = model::File { content };
+        let id = file.get_hash();
+        // Sent with the next batch, which goes out before any tree that
+        // could reference the file
+        let id = FileId::from_bytes(&id);
+        let mut pending = self.pending_files.lock().unwrap();
+        pending.bytes += file.content.len();
+        pending.files.insert(id.clone(), file);
+        if pending.bytes >= PENDING_FILES_BYTES {
+            self.flush_files(&mut pending)?;
+        }
+        Ok(id)
     }
 
     async fn read_symlink(&self, _path: &RepoPath, id: &SymlinkId) -> BackendResult<String> {
+        let req = symlink_id_to_proto(id);
         let proto = self
-            .client
-            .read_symlink(symlink_id_to_proto(id))
-            .unwrap()
+            .pool
+            .call(move |mut client| {
+                let req = req.clone();
+                async move { client.read_symlink(req).await }
+            })
+            .await
+            .map_err(|status| read_error("symlink", id, status))?
             .into_inner();
-        Ok(symlink_from_proto(proto))
+        Ok(model::Symlink::from(proto).target)
     }
 
     fn write_symlink(&self, _path: &RepoPath, target: &str) -> BackendResult<SymlinkId> {
-        let proto = symlink_to_proto(target);
-        let id = self.client.write_symlink(proto).unwrap();
+        let symlink = model::Symlink {
+            target: target.to_string(),
+        };
+        let req = proto::jj_interface::WriteSymlinkReq {
+            expected_id: Some(proto::jj_interface::SymlinkId {
+                symlink_id: symlink.get_hash().to_vec(),
+            }),
+            symlink: Some(symlink.as_proto()),
+        };
+        let id = self
+            .client
+            .write_symlink(req)
+            .map_err(|status| write_error("symlink", status))?;
         let id = id.into_inner();
         Ok(SymlinkId::new(id.symlink_id))
     }
@@ -124,19 +382,40 @@ impl Backend for CultivateBackend {
     #[tracing::instrument]
     async fn read_tree(&self, _path: &RepoPath, id: &TreeId) -> BackendResult<Tree> {
         tracing::error!(id = ?id);
+        if let Some(tree) = self.take_prefetched_tree(id) {
+            return Ok(tree);
+        }
+        let req = tree_id_to_proto(id);
         let proto = self
-            .client
-            .read_tree(tree_id_to_proto(id))
-            .unwrap()
+            .pool
+            .call(move |mut client| {
+                let req = req.clone();
+                async move { client.read_tree(req).await }
+            })
+            .await
+            .map_err(|status| read_error("tree", id, status))?
             .into_inner();
-        Ok(tree_from_proto(proto))
+        let tree = model::Tree::try_from(proto).map_err(|err| decode_error("tree", id, err))?;
+        let tree = tree_from_model(tree);
+        self.prefetch_descendants(&tree).await;
+        Ok(tree)
     }
 
     #[tracing::instrument]
     fn write_tree(&self, _path: &RepoPath, tree: &Tree) -> BackendResult<TreeId> {
         tracing::error!(tree = ?tree);
-        let proto = tree_to_proto(tree);
-        let id = self.client.write_tree(proto).unwrap();
+        self.flush_files(&mut self.pending_files.lock().unwrap())?;
+        let tree = tree_to_model(tree)?;
+        let req = proto::jj_interface::WriteTreeReq {
+            expected_id: Some(proto::jj_interface::TreeId {
+                tree_id: tree.get_hash().to_vec(),
+            }),
+            tree: Some(tree.as_proto()),
+        };
+        let id = self
+            .client
+            .write_tree(req)
+            .map_err(|status| write_error("tree", status))?;
         let id = id.into_inner();
         Ok(TreeId::new(id.tree_id))
     }
@@ -156,12 +435,19 @@ impl Backend for CultivateBackend {
                 self.empty_tree_id.clone(),
             ));
         }
+        let req = commit_id_to_proto(id);
         let proto = self
-            .client
-            .read_commit(commit_id_to_proto(id))
-            .unwrap()
+            .pool
+            .call(move |mut client| {
+                let req = req.clone();
+                async move { client.read_commit(req).await }
+            })
+            .await
+            .map_err(|status| read_error("commit", id, status))?
             .into_inner();
-        Ok(commit_from_proto(proto))
+        let commit =
+            model::Commit::try_from(proto).map_err(|err| decode_error("commit", id, err))?;
+        commit_from_model(id, commit)
     }
 
     fn write_commit(
@@ -177,8 +463,17 @@ impl Backend for CultivateBackend {
                 "Cannot write a commit with no parents".into(),
             ));
         }
-        let proto = commit_to_proto(&commit);
-        let id = self.client.write_commit(proto).unwrap();
+        let model = commit_to_model(&commit)?;
+        let req = proto::jj_interface::WriteCommitReq {
+            expected_id: Some(proto::jj_interface::CommitId {
+                commit_id: model.get_hash().to_vec(),
+            }),
+            commit: Some(model.as_proto()),
+        };
+        let id = self
+            .client
+            .write_commit(req)
+            .map_err(|status| write_error("commit", status))?;
         let id = id.into_inner();
         Ok((CommitId::new(id.commit_id), commit))
     }
@@ -192,204 +487,261 @@ impl Backend for CultivateBackend {
         _paths: &[RepoPathBuf],
         _roots: &[CommitId],
         _heads: &[CommitId],
-    ) -> BackendResult<BoxStream<BackendResult<CopyRecord>>> {
+    ) -> BackendResult<BoxStream<'_, BackendResult<CopyRecord>>> {
         todo!()
     }
 }
 
+/// The id of the repo whose store is at `store_path`, `None` for repos
+/// created before the daemon handed out ids.
+pub fn read_repo_id(store_path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(store_path.join(REPO_ID_FILE)) {
+        Ok(repo_id) => Ok(Some(repo_id)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
 pub fn file_id_to_proto(file_id: &FileId) -> proto::jj_interface::FileId {
-    let mut proto = proto::jj_interface::FileId::default();
-    proto.file_id = file_id.to_bytes();
-    proto
+    proto::jj_interface::FileId {
+        file_id: file_id.to_bytes(),
+    }
 }
 
 pub fn commit_id_to_proto(commit_id: &CommitId) -> proto::jj_interface::CommitId {
-    let mut proto = proto::jj_interface::CommitId::default();
-    proto.commit_id = commit_id.to_bytes();
-    proto
+    proto::jj_interface::CommitId {
+        commit_id: commit_id.to_bytes(),
+    }
 }
 
 pub fn tree_id_to_proto(tree_id: &TreeId) -> proto::jj_interface::TreeId {
-    let mut proto = proto::jj_interface::TreeId::default();
-    proto.tree_id = tree_id.to_bytes();
-    proto
+    proto::jj_interface::TreeId {
+        tree_id: tree_id.to_bytes(),
+    }
 }
 
 pub fn symlink_id_to_proto(symlink_id: &SymlinkId) -> proto::jj_interface::SymlinkId {
-    let mut proto = proto::jj_interface::SymlinkId::default();
-    proto.symlink_id = symlink_id.to_bytes();
-    proto
+    proto::jj_interface::SymlinkId {
+        symlink_id: symlink_id.to_bytes(),
+    }
 }
 
-pub fn commit_to_proto(commit: &Commit) -> proto::jj_interface::Commit {
-    let mut proto = proto::jj_interface::Commit::default();
-    for parent in &commit.parents {
-        proto.parents.push(parent.to_bytes());
-    }
-    for predecessor in &commit.predecessors {
-        proto.predecessors.push(predecessor.to_bytes());
-    }
-    match &commit.root_tree {
-        MergedTreeId::Legacy(tree_id) => {
-            proto.root_tree = vec![tree_id.to_bytes()];
+fn to_model_id(id: &impl ObjectId) -> BackendResult<model::Id> {
+    id.as_bytes()
+        .try_into()
+        .map_err(|_| BackendError::InvalidHashLength {
+            expected: std::mem::size_of::<model::Id>(),
+            actual: id.as_bytes().len(),
+            object_type: id.object_type(),
+            hash: id.hex(),
+        })
+}
+
+fn to_model_ids<'a, T: ObjectId + 'a>(
+    ids: impl IntoIterator<Item = &'a T>,
+) -> BackendResult<Vec<model::Id>> {
+    ids.into_iter().map(to_model_id).collect()
+}
+
+/// A daemon that doesn't have an object says so with `NotFound`, anything
+/// else is a failure to read it.
+fn read_error(object_type: &str, id: &impl ObjectId, status: tonic::Status) -> BackendError {
+    if status.code() == tonic::Code::NotFound {
+        BackendError::ObjectNotFound {
+            object_type: object_type.to_string(),
+            hash: id.hex(),
+            source: status.into(),
         }
-        MergedTreeId::Merge(tree_ids) => {
-            proto.uses_tree_conflict_format = true;
-            proto.root_tree = tree_ids.iter().map(|id| id.to_bytes()).collect();
+    } else {
+        BackendError::ReadObject {
+            object_type: object_type.to_string(),
+            hash: id.hex(),
+            source: status.into(),
         }
     }
-    proto.change_id = commit.change_id.to_bytes();
-    proto.description = commit.description.clone();
-    proto.author = Some(signature_to_proto(&commit.author));
-    proto.committer = Some(signature_to_proto(&commit.committer));
-    proto
 }
 
-fn commit_from_proto(mut proto: proto::jj_interface::Commit) -> Commit {
-    // Note how .take() sets the secure_sig field to None before we encode the data.
-    // Needs to be done first since proto is partially moved a bunch below
-    let secure_sig = proto.secure_sig.take().map(|sig| SecureSig {
-        data: proto.encode_to_vec(),
+fn decode_error(object_type: &str, id: &impl ObjectId, err: model::ProtoError) -> BackendError {
+    BackendError::ReadObject {
+        object_type: object_type.to_string(),
+        hash: id.hex(),
+        source: err.into(),
+    }
+}
+
+fn write_error(object_type: &'static str, status: tonic::Status) -> BackendError {
+    BackendError::WriteObject {
+        object_type,
+        source: status.into(),
+    }
+}
+
+fn commit_to_model(commit: &Commit) -> BackendResult<model::Commit> {
+    let (root_tree, uses_tree_conflict_format) = match &commit.root_tree {
+        MergedTreeId::Legacy(tree_id) => (vec![to_model_id(tree_id)?], false),
+        MergedTreeId::Merge(tree_ids) => (to_model_ids(tree_ids.iter())?, true),
+    };
+    Ok(model::Commit {
+        parents: to_model_ids(&commit.parents)?,
+        predecessors: to_model_ids(&commit.predecessors)?,
+        root_tree,
+        uses_tree_conflict_format,
+        change_id: commit.change_id.to_bytes(),
+        description: commit.description.clone(),
+        author: signature_to_model(&commit.author),
+        committer: signature_to_model(&commit.committer),
+        secure_sig: None,
+    })
+}
+
+fn commit_from_model(id: &CommitId, mut commit: model::Commit) -> BackendResult<Commit> {
+    // The signed data is the commit encoded without its signature.
+    let secure_sig = commit.secure_sig.take().map(|sig| SecureSig {
+        data: commit.as_proto().encode_to_vec(),
         sig,
     });
 
-    let parents = proto.parents.into_iter().map(CommitId::new).collect();
-    let predecessors = proto.predecessors.into_iter().map(CommitId::new).collect();
-    let root_tree = if proto.uses_tree_conflict_format {
-        let merge_builder: MergeBuilder<_> = proto.root_tree.into_iter().map(TreeId::new).collect();
+    let parents = commit
+        .parents
+        .iter()
+        .map(|id| CommitId::from_bytes(id))
+        .collect();
+    let predecessors = commit
+        .predecessors
+        .iter()
+        .map(|id| CommitId::from_bytes(id))
+        .collect();
+    let invalid_root_tree = |expected: &str| BackendError::ReadObject {
+        object_type: "commit".to_string(),
+        hash: id.hex(),
+        source: format!(
+            "Commit has {} root trees, expected {expected}",
+            commit.root_tree.len()
+        )
+        .into(),
+    };
+    let root_tree = if commit.uses_tree_conflict_format {
+        // A merge alternates adds and removes, starting and ending with an add
+        if commit.root_tree.len().is_multiple_of(2) {
+            return Err(invalid_root_tree("an odd number"));
+        }
+        let merge_builder: MergeBuilder<_> = commit
+            .root_tree
+            .iter()
+            .map(|id| TreeId::from_bytes(id))
+            .collect();
         MergedTreeId::Merge(merge_builder.build())
     } else {
-        assert_eq!(proto.root_tree.len(), 1);
-        MergedTreeId::Legacy(TreeId::new(proto.root_tree[0].to_vec()))
+        let [tree_id] = commit.root_tree.as_slice() else {
+            return Err(invalid_root_tree("one"));
+        };
+        MergedTreeId::Legacy(TreeId::from_bytes(tree_id))
     };
-    let change_id = ChangeId::new(proto.change_id);
-    Commit {
+    Ok(Commit {
         parents,
         predecessors,
         root_tree,
-        change_id,
-        description: proto.description,
-        author: signature_from_proto(proto.author.unwrap_or_default()),
-        committer: signature_from_proto(proto.committer.unwrap_or_default()),
+        change_id: ChangeId::new(commit.change_id),
+        description: commit.description,
+        author: signature_from_model(commit.author),
+        committer: signature_from_model(commit.committer),
         secure_sig,
-    }
+    })
 }
-fn signature_to_proto(signature: &Signature) -> proto::jj_interface::commit::Signature {
-    proto::jj_interface::commit::Signature {
+
+fn signature_to_model(signature: &Signature) -> model::Signature {
+    model::Signature {
         name: signature.name.clone(),
         email: signature.email.clone(),
-        timestamp: Some(proto::jj_interface::commit::Timestamp {
+        timestamp: model::Timestamp {
             millis_since_epoch: signature.timestamp.timestamp.0,
             tz_offset: signature.timestamp.tz_offset,
-        }),
+        },
     }
 }
 
-fn signature_from_proto(proto: proto::jj_interface::commit::Signature) -> Signature {
-    let timestamp = proto.timestamp.unwrap_or_default();
+fn signature_from_model(signature: model::Signature) -> Signature {
     Signature {
-        name: proto.name,
-        email: proto.email,
+        name: signature.name,
+        email: signature.email,
         timestamp: Timestamp {
-            timestamp: MillisSinceEpoch(timestamp.millis_since_epoch),
-            tz_offset: timestamp.tz_offset,
+            timestamp: MillisSinceEpoch(signature.timestamp.millis_since_epoch),
+            tz_offset: signature.timestamp.tz_offset,
         },
     }
 }
 
-fn file_to_proto(file: &mut dyn Read) -> proto::jj_interface::File {
-    let mut proto = proto::jj_interface::File::default();
-    let mut out = vec![];
-    zstd::stream::copy_encode(file, &mut out, 0).unwrap();
-    proto.data = out;
-    proto
+fn tree_to_model(tree: &Tree) -> BackendResult<model::Tree> {
+    let entries = tree
+        .entries()
+        .map(|entry| {
+            let value = tree_value_to_model(entry.value())?;
+            Ok((entry.name().as_str().to_owned(), value))
+        })
+        .collect::<BackendResult<_>>()?;
+    Ok(model::Tree { entries })
 }
 
-fn tree_to_proto(tree: &Tree) -> proto::jj_interface::Tree {
-    let mut proto = proto::jj_interface::Tree::default();
-    for entry in tree.entries() {
-        proto.entries.push(proto::jj_interface::tree::Entry {
-            name: entry.name().as_str().to_owned(),
-            value: Some(tree_value_to_proto(entry.value())),
-        });
+fn tree_from_model(tree: model::Tree) -> Tree {
+    let mut jj_tree = Tree::default();
+    for (name, entry) in tree.entries {
+        jj_tree.set(
+            RepoPathComponentBuf::from(name),
+            tree_value_from_model(entry),
+        );
     }
-    proto
-}
-
-fn symlink_to_proto(target: &str) -> proto::jj_interface::Symlink {
-    let mut proto = proto::jj_interface::Symlink::default();
-    proto.target = target.to_string();
-    proto
+    jj_tree
 }
 
-fn symlink_from_proto(proto: proto::jj_interface::Symlink) -> String {
-    proto.target.to_string()
-}
-
-fn tree_value_to_proto(value: &TreeValue) -> proto::jj_interface::TreeValue {
-    let mut proto = proto::jj_interface::TreeValue::default();
-    match value {
-        TreeValue::File { id, executable } => {
-            proto.value = Some(proto::jj_interface::tree_value::Value::File(
-                proto::jj_interface::tree_value::File {
-                    id: id.to_bytes(),
-                    executable: *executable,
-                },
-            ));
-        }
-        TreeValue::Symlink(id) => {
-            proto.value = Some(proto::jj_interface::tree_value::Value::SymlinkId(
-                id.to_bytes(),
-            ));
-        }
+fn tree_value_to_model(value: &TreeValue) -> BackendResult<model::TreeEntry> {
+    Ok(match value {
+        TreeValue::File { id, executable } => model::TreeEntry::File {
+            id: to_model_id(id)?,
+            executable: *executable,
+        },
+        TreeValue::Symlink(id) => model::TreeEntry::SymlinkId(to_model_id(id)?),
+        TreeValue::Tree(id) => model::TreeEntry::TreeId(to_model_id(id)?),
+        TreeValue::Conflict(id) => model::TreeEntry::ConflictId(to_model_id(id)?),
         TreeValue::GitSubmodule(_id) => {
-            panic!("cannot store git submodules");
-        }
-        TreeValue::Tree(id) => {
-            proto.value = Some(proto::jj_interface::tree_value::Value::TreeId(
-                id.to_bytes(),
-            ));
+            return Err(BackendError::Unsupported(
+                "cannot store git submodules".to_string(),
+            ))
         }
-        TreeValue::Conflict(id) => {
-            proto.value = Some(proto::jj_interface::tree_value::Value::ConflictId(
-                id.to_bytes(),
-            ));
-        }
-    }
-    proto
+    })
 }
 
-fn file_from_proto(proto: proto::jj_interface::File) -> Box<dyn Read> {
-    let mut file = vec![];
-    zstd::stream::copy_decode(proto.data.as_slice(), &mut file).unwrap();
-    Box::new(Cursor::new(file))
-}
-
-fn tree_from_proto(proto: proto::jj_interface::Tree) -> Tree {
-    let mut tree = Tree::default();
-    for proto_entry in proto.entries {
-        let value = tree_value_from_proto(proto_entry.value.unwrap());
-        tree.set(RepoPathComponentBuf::from(proto_entry.name), value);
+fn tree_value_from_model(entry: model::TreeEntry) -> TreeValue {
+    match entry {
+        model::TreeEntry::File { id, executable } => TreeValue::File {
+            id: FileId::from_bytes(&id),
+            executable,
+        },
+        model::TreeEntry::TreeId(id) => TreeValue::Tree(TreeId::from_bytes(&id)),
+        model::TreeEntry::SymlinkId(id) => TreeValue::Symlink(SymlinkId::from_bytes(&id)),
+        model::TreeEntry::ConflictId(id) => TreeValue::Conflict(ConflictId::from_bytes(&id)),
     }
-    tree
 }
 
-fn tree_value_from_proto(proto: proto::jj_interface::TreeValue) -> TreeValue {
-    match proto.value.unwrap() {
-        proto::jj_interface::tree_value::Value::TreeId(id) => TreeValue::Tree(TreeId::new(id)),
-        proto::jj_interface::tree_value::Value::File(proto::jj_interface::tree_value::File {
-            id,
-            executable,
-            ..
-        }) => TreeValue::File {
-            id: FileId::new(id),
-            executable,
-        },
-        proto::jj_interface::tree_value::Value::SymlinkId(id) => {
-            TreeValue::Symlink(SymlinkId::new(id))
-        }
-        proto::jj_interface::tree_value::Value::ConflictId(id) => {
-            TreeValue::Conflict(ConflictId::new(id))
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_without_daemon() {
+        // Nothing listens on the discard port
+        let config = config::Config::builder()
+            .set_override("cultivate.address", "http://127.0.0.1:9")
+            .unwrap()
+            .set_override("cultivate.timeout", 1)
+            .unwrap()
+            .build()
+            .unwrap();
+        let settings = UserSettings::from_config(config);
+        let store_path = Path::new("missing-store");
+
+        let err = CultivateBackend::load(&settings, store_path).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cultivate daemon not reachable at http://127.0.0.1:9"));
     }
 }
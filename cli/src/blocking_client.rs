@@ -1,156 +1,136 @@
-use std::sync::{Arc, Mutex};
+// tonic::Status is large, but boxing it would only obscure these thin wrappers.
+#![allow(clippy::result_large_err)]
 
-use proto::jj_interface::{jujutsu_interface_client::JujutsuInterfaceClient, *};
-use tokio::runtime::{Builder, Runtime};
+use proto::{jj_interface::*, Address};
+
+use crate::client::{ClientPool, RetryPolicy};
 
 type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
 type Result<T, E = StdError> = ::std::result::Result<T, E>;
 
-// The order of the fields in this struct is important. They must be ordered
-// such that when `BlockingJujutsuInterfaceClient` is dropped the client is dropped
-// before the runtime. Not doing this will result in a deadlock when dropped.
-// Rust drops struct fields in declaration order.
+/// Synchronous calls for the parts of jj that are not async, blocking on the
+/// pool's runtime.
 #[derive(Debug, Clone)]
 pub struct BlockingJujutsuInterfaceClient {
-    client: Arc<Mutex<JujutsuInterfaceClient<tonic::transport::Channel>>>,
-    rt: Arc<Mutex<Runtime>>,
+    pool: ClientPool,
 }
 
 impl BlockingJujutsuInterfaceClient {
-    pub fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
-    where
-        D: TryInto<tonic::transport::Endpoint>,
-        D::Error: Into<StdError>,
-    {
-        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
-        let client = Arc::new(Mutex::new(
-            rt.block_on(JujutsuInterfaceClient::connect(dst))?,
-        ));
-        let rt = Arc::new(Mutex::new(rt));
-
-        Ok(Self { client, rt })
+    pub fn new(pool: ClientPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn connect(address: &Address) -> Result<Self, tonic::transport::Error> {
+        Ok(Self::new(ClientPool::connect(
+            address,
+            1,
+            RetryPolicy::default(),
+        )?))
     }
 
     pub fn get_tree_state(
         &self,
-        request: impl tonic::IntoRequest<GetTreeStateReq>,
+        request: GetTreeStateReq,
     ) -> Result<tonic::Response<GetTreeStateReply>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.get_tree_state(request))
+        self.pool.block_on(|mut client| {
+            let request = request.clone();
+            async move { client.get_tree_state(request).await }
+        })
     }
 
     pub fn initialize(
         &self,
-        request: impl tonic::IntoRequest<InitializeReq>,
+        request: InitializeReq,
     ) -> Result<tonic::Response<InitializeReply>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.initialize(request))
+        self.pool
+            .block_on_once(|mut client| async move { client.initialize(request).await })
     }
 
     pub fn set_checkout_state(
         &self,
-        request: impl tonic::IntoRequest<SetCheckoutStateReq>,
+        request: SetCheckoutStateReq,
     ) -> Result<tonic::Response<SetCheckoutStateReply>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.set_checkout_state(request))
+        self.pool
+            .block_on_once(|mut client| async move { client.set_checkout_state(request).await })
     }
 
     pub fn get_checkout_state(
         &self,
-        request: impl tonic::IntoRequest<GetCheckoutStateReq>,
+        request: GetCheckoutStateReq,
     ) -> Result<tonic::Response<CheckoutState>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.get_checkout_state(request))
+        self.pool.block_on(|mut client| {
+            let request = request.clone();
+            async move { client.get_checkout_state(request).await }
+        })
     }
 
     pub fn snapshot(
         &self,
-        request: impl tonic::IntoRequest<SnapshotReq>,
+        request: SnapshotReq,
     ) -> Result<tonic::Response<SnapshotReply>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.snapshot(request))
+        self.pool
+            .block_on_once(|mut client| async move { client.snapshot(request).await })
     }
 
     pub fn write_commit(
         &self,
-        request: impl tonic::IntoRequest<Commit>,
+        request: WriteCommitReq,
     ) -> Result<tonic::Response<CommitId>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.write_commit(request))
-    }
-
-    pub fn read_commit(
-        &self,
-        request: impl tonic::IntoRequest<CommitId>,
-    ) -> Result<tonic::Response<Commit>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.read_commit(request))
-    }
-
-    pub fn write_file(
-        &self,
-        request: impl tonic::IntoRequest<File>,
-    ) -> Result<tonic::Response<FileId>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.write_file(request))
+        self.pool.block_on(|mut client| {
+            let request = request.clone();
+            async move { client.write_commit(request).await }
+        })
     }
 
-    pub fn read_file(
+    pub fn write_files(
         &self,
-        request: impl tonic::IntoRequest<FileId>,
-    ) -> Result<tonic::Response<File>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.read_file(request))
+        request: WriteFilesReq,
+    ) -> Result<tonic::Response<WriteFilesReply>, tonic::Status> {
+        self.pool.block_on(|mut client| {
+            let request = request.clone();
+            async move { client.write_files(request).await }
+        })
     }
 
     pub fn write_tree(
         &self,
-        request: impl tonic::IntoRequest<Tree>,
+        request: WriteTreeReq,
     ) -> Result<tonic::Response<TreeId>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.write_tree(request))
-    }
-
-    pub fn read_tree(
-        &self,
-        request: impl tonic::IntoRequest<TreeId>,
-    ) -> Result<tonic::Response<Tree>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.read_tree(request))
+        self.pool.block_on(|mut client| {
+            let request = request.clone();
+            async move { client.write_tree(request).await }
+        })
     }
 
     pub fn write_symlink(
         &self,
-        request: impl tonic::IntoRequest<Symlink>,
+        request: WriteSymlinkReq,
     ) -> Result<tonic::Response<SymlinkId>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.write_symlink(request))
+        self.pool.block_on(|mut client| {
+            let request = request.clone();
+            async move { client.write_symlink(request).await }
+        })
     }
 
-    pub fn read_symlink(
+    pub fn find_missing(
         &self,
-        request: impl tonic::IntoRequest<SymlinkId>,
-    ) -> Result<tonic::Response<Symlink>, tonic::Status> {
-        let mut client = self.client.lock().unwrap();
-        let rt = self.rt.lock().unwrap();
-        rt.block_on(client.read_symlink(request))
+        request: FindMissingReq,
+    ) -> Result<tonic::Response<FindMissingReply>, tonic::Status> {
+        self.pool.block_on(|mut client| {
+            let request = request.clone();
+            async move { client.find_missing(request).await }
+        })
+    }
+
+    pub fn concurrency(&self) -> Result<tonic::Response<ConcurrencyReply>, tonic::Status> {
+        self.pool.block_on(|mut client| async move {
+            client.concurrency(ConcurrencyRequest::default()).await
+        })
     }
 
     pub fn get_empty_tree_id(&self) -> Result<tonic::Response<TreeId>, tonic::Status> {
-        let rt = self.rt.lock().unwrap();
-        let mut client = self.client.lock().unwrap();
-        rt.block_on(client.get_empty_tree_id(GetEmptyTreeIdReq::default()))
+        self.pool.block_on(|mut client| async move {
+            client.get_empty_tree_id(GetEmptyTreeIdReq::default()).await
+        })
     }
 }
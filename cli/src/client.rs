@@ -0,0 +1,378 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use jj_lib::settings::UserSettings;
+use proto::{
+    control::control_client::ControlClient,
+    health::{
+        health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+    },
+    jj_interface::jujutsu_interface_client::JujutsuInterfaceClient,
+    Address,
+};
+use tokio::{
+    net::UnixStream,
+    runtime::{Builder, Runtime},
+};
+use tonic::{
+    codegen::InterceptedService,
+    metadata::{errors::InvalidMetadataValue, AsciiMetadataValue},
+    service::Interceptor,
+    transport::{Channel, Endpoint, Uri},
+    Code, Request, Status,
+};
+use tracing::debug;
+
+pub type Client = JujutsuInterfaceClient<InterceptedService<Channel, RepoScope>>;
+pub type Control = ControlClient<Channel>;
+
+/// The daemon to talk to: the environment override, then the
+/// `cultivate.address` setting, then the default.
+pub fn daemon_address(settings: &UserSettings) -> Address {
+    std::env::var(proto::ADDRESS_ENV)
+        .ok()
+        .or_else(|| settings.config().get_string("cultivate.address").ok())
+        .map_or_else(proto::default_address, |address| address.as_str().into())
+}
+
+/// How calls to the daemon are retried. Only idempotent calls (reads and
+/// content-addressed writes) are ever retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts made before giving up, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Time allowed for each attempt.
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connections {
+    channels: Vec<Channel>,
+    next: AtomicUsize,
+    address: String,
+    policy: RetryPolicy,
+}
+
+impl Connections {
+    fn channel(&self) -> Channel {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        self.channels[next % self.channels.len()].clone()
+    }
+
+    /// Calls `f` until it succeeds, fails in a way retrying won't fix, or
+    /// runs out of attempts. Channels reconnect on their own, so a retry
+    /// after the daemon restarts goes to the new daemon.
+    async fn call<F, Fut, T>(&self, mut f: F, retry: bool) -> Result<T, Status>
+    where
+        F: FnMut(Channel) -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let max_attempts = if retry { self.policy.max_attempts } else { 1 };
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let status = match tokio::time::timeout(self.policy.timeout, f(self.channel())).await {
+                Ok(Err(status)) if is_transient(&status) => status,
+                Ok(result) => return result,
+                Err(_) => {
+                    Status::deadline_exceeded(format!("No reply within {:?}", self.policy.timeout))
+                }
+            };
+            if attempt >= max_attempts {
+                return Err(Status::unavailable(format!(
+                    "cultivate daemon not reachable at {}: {}",
+                    self.address,
+                    status.message()
+                )));
+            }
+            debug!(attempt, ?backoff, %status, "Retrying call to daemon");
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.policy.max_backoff);
+            attempt += 1;
+        }
+    }
+}
+
+/// Names the repository every `JujutsuInterface` call is for. Calls of an
+/// unscoped pool go to the daemon's default repository.
+#[derive(Debug, Clone, Default)]
+pub struct RepoScope(Option<AsciiMetadataValue>);
+
+impl Interceptor for RepoScope {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(repo_id) = &self.0 {
+            request
+                .metadata_mut()
+                .insert(proto::REPO_ID_METADATA, repo_id.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Failures of the connection rather than of the call itself.
+fn is_transient(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded)
+}
+
+/// A fixed set of connections to the daemon, handed out round-robin.
+///
+/// Calls run on the pool's own runtime. jj drives the async `Backend` methods
+/// with `pollster`, not tokio, so awaiting a `ClientPool::call` is safe from
+/// any executor and never nests runtimes.
+// The order of the fields in this struct is important. They must be ordered
+// such that when `ClientPool` is dropped the clients are dropped before the
+// runtime. Not doing this will result in a deadlock when dropped. Rust drops
+// struct fields in declaration order.
+#[derive(Debug, Clone)]
+pub struct ClientPool {
+    connections: Arc<Connections>,
+    scope: RepoScope,
+    rt: Arc<Runtime>,
+}
+
+impl ClientPool {
+    /// Sets up `size` channels to `address`. Channels connect on first use,
+    /// so an unreachable daemon is reported by the first call.
+    pub fn connect(
+        address: &Address,
+        size: usize,
+        policy: RetryPolicy,
+    ) -> Result<Self, tonic::transport::Error> {
+        assert!(size > 0, "connection pool must not be empty");
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+
+        let _guard = rt.enter();
+        let channels = match address {
+            Address::Tcp(uri) => {
+                let endpoint = Endpoint::from_shared(uri.clone())?.connect_timeout(policy.timeout);
+                (0..size).map(|_| endpoint.connect_lazy()).collect()
+            }
+            Address::Unix(path) => {
+                // The URI is required but unused, the connector ignores it.
+                let endpoint = Endpoint::from_static("http://[::]:0");
+                (0..size)
+                    .map(|_| {
+                        let path = path.clone();
+                        let connector =
+                            tower::service_fn(move |_: Uri| UnixStream::connect(path.clone()));
+                        endpoint.connect_with_connector_lazy(connector)
+                    })
+                    .collect()
+            }
+        };
+        let connections = Connections {
+            channels,
+            next: Default::default(),
+            address: address.to_string(),
+            policy,
+        };
+
+        Ok(Self {
+            connections: Arc::new(connections),
+            scope: RepoScope::default(),
+            rt: Arc::new(rt),
+        })
+    }
+
+    /// Scopes every call to the repository `repo_id`, sharing the
+    /// connections of `self`.
+    pub fn with_repo_id(self, repo_id: &str) -> Result<Self, InvalidMetadataValue> {
+        Ok(Self {
+            scope: RepoScope(Some(repo_id.parse()?)),
+            ..self
+        })
+    }
+
+    /// A single connection that gives up right away, so a missing daemon is
+    /// reported as such instead of being retried.
+    pub fn connect_once(address: &Address) -> Result<Self, tonic::transport::Error> {
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        };
+        Self::connect(address, 1, policy)
+    }
+
+    pub fn size(&self) -> usize {
+        self.connections.channels.len()
+    }
+
+    /// Runs the idempotent call `f` on the pool's runtime and awaits its
+    /// result, retrying according to the pool's policy.
+    pub async fn call<F, Fut, T>(&self, f: F) -> Result<T, Status>
+    where
+        F: FnMut(Client) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Status>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let connections = self.connections.clone();
+        let scope = self.scope.clone();
+        let mut f = f;
+        self.rt
+            .spawn(async move {
+                connections
+                    .call(
+                        |channel| {
+                            f(JujutsuInterfaceClient::with_interceptor(
+                                channel,
+                                scope.clone(),
+                            ))
+                        },
+                        true,
+                    )
+                    .await
+            })
+            .await
+            .map_err(|err| Status::internal(format!("Client task failed: {err}")))?
+    }
+
+    /// Blocks the current thread on the idempotent call `f`, retrying
+    /// according to the pool's policy. Must not be called from within an
+    /// async context.
+    #[allow(clippy::result_large_err)]
+    pub fn block_on<F, Fut, T>(&self, mut f: F) -> Result<T, Status>
+    where
+        F: FnMut(Client) -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        self.rt.block_on(
+            self.connections
+                .call(|channel| f(self.client(channel)), true),
+        )
+    }
+
+    /// Like `block_on`, but for calls that must not be repeated.
+    #[allow(clippy::result_large_err)]
+    pub fn block_on_once<F, Fut, T>(&self, f: F) -> Result<T, Status>
+    where
+        F: FnOnce(Client) -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let mut f = Some(f);
+        self.rt.block_on(self.connections.call(
+            |channel| f.take().expect("called once")(self.client(channel)),
+            false,
+        ))
+    }
+
+    fn client(&self, channel: Channel) -> Client {
+        JujutsuInterfaceClient::with_interceptor(channel, self.scope.clone())
+    }
+
+    /// Blocks the current thread on a call to the daemon's control service.
+    /// Like `block_on_once`, the call is never repeated.
+    #[allow(clippy::result_large_err)]
+    pub fn control<F, Fut, T>(&self, f: F) -> Result<T, Status>
+    where
+        F: FnOnce(Control) -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        self.call_once(Control::new, f)
+    }
+
+    /// Asks the daemon whether `service` is ready, the empty name standing for
+    /// the daemon as a whole.
+    #[allow(clippy::result_large_err)]
+    pub fn health(&self, service: &str) -> Result<ServingStatus, Status> {
+        let service = service.to_string();
+        self.call_once(HealthClient::new, |mut health| async move {
+            let reply = health.check(HealthCheckRequest { service }).await?;
+            Ok(reply.into_inner().status())
+        })
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn call_once<C, F, Fut, T>(&self, new: fn(Channel) -> C, f: F) -> Result<T, Status>
+    where
+        F: FnOnce(C) -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let mut f = Some(f);
+        self.rt.block_on(self.connections.call(
+            |channel| f.take().expect("called once")(new(channel)),
+            false,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proto::jj_interface::GetEmptyTreeIdReq;
+
+    use super::*;
+
+    #[test]
+    fn unreachable_daemon() {
+        // Nothing listens on the discard port
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        // Without a scheme, as the daemon accepts it
+        let pool = ClientPool::connect(&"127.0.0.1:9".into(), 2, policy).unwrap();
+
+        let mut attempts = 0;
+        let status = pool
+            .block_on(|mut client| {
+                attempts += 1;
+                async move { client.get_empty_tree_id(GetEmptyTreeIdReq {}).await }
+            })
+            .unwrap_err();
+        assert_eq!(attempts, 3);
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(status
+            .message()
+            .starts_with("cultivate daemon not reachable at http://127.0.0.1:9:"));
+
+        let mut attempts = 0;
+        pool.block_on_once(|mut client| {
+            attempts += 1;
+            async move { client.get_empty_tree_id(GetEmptyTreeIdReq {}).await }
+        })
+        .unwrap_err();
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn unix_socket() {
+        let dir = std::env::temp_dir().join(format!("cultivate-client-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let address = Address::Unix(dir.join("daemon.sock"));
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let pool = ClientPool::connect(&address, 1, policy).unwrap();
+        let status = pool
+            .block_on(
+                |mut client| async move { client.get_empty_tree_id(GetEmptyTreeIdReq {}).await },
+            )
+            .unwrap_err();
+        assert_eq!(
+            status.message().split(": ").next().unwrap(),
+            format!("cultivate daemon not reachable at {address}")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use jj_cli::{
+    command_error::{user_error, CommandError},
+    ui::Ui,
+};
+use jj_lib::{backend::TreeId, object_id::ObjectId, settings::UserSettings};
+use proto::{
+    control::{DaemonStatusReply, DaemonStatusReq, ListMountsReq, ShutdownReq, StoreStatsReq},
+    health::health_check_response::ServingStatus,
+    Address,
+};
+use tonic::{Code, Status};
+
+use crate::{
+    autostart,
+    client::{self, ClientPool},
+};
+
+/// How long a daemon has to unmount everything and exit once asked to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum DaemonCommand {
+    /// Start a local daemon unless one is already running
+    Start,
+    /// Unmount all working copies and stop the daemon
+    Stop,
+    /// Stop the daemon, then start a new one
+    Restart,
+    /// Show whether the daemon is running and what it serves
+    Status,
+}
+
+pub fn run(
+    ui: &mut Ui,
+    settings: &UserSettings,
+    command: DaemonCommand,
+) -> Result<(), CommandError> {
+    let address = client::daemon_address(settings);
+    match command {
+        DaemonCommand::Start => start(ui, settings, &address),
+        DaemonCommand::Stop => stop(ui, &address),
+        DaemonCommand::Restart => {
+            stop(ui, &address)?;
+            start(ui, settings, &address)
+        }
+        DaemonCommand::Status => status(ui, &address),
+    }
+}
+
+fn start(ui: &mut Ui, settings: &UserSettings, address: &Address) -> Result<(), CommandError> {
+    if autostart::is_ready(&connect(address)?) {
+        writeln!(ui.status(), "Daemon already running at {address}")?;
+        return Ok(());
+    }
+    autostart::start(settings, address).map_err(user_error)?;
+    writeln!(ui.status(), "Started daemon at {address}")?;
+    Ok(())
+}
+
+fn stop(ui: &mut Ui, address: &Address) -> Result<(), CommandError> {
+    let reply = connect(address)?
+        .control(|mut control| async move { control.shutdown(ShutdownReq {}).await });
+    match reply {
+        Ok(_) => {}
+        Err(status) if is_unreachable(&status) => {
+            if let Address::Unix(socket) = address {
+                // A daemon that died without cleaning up leaves its pidfile
+                let _ = std::fs::remove_file(proto::pidfile_path(socket));
+            }
+            writeln!(ui.status(), "Daemon not running at {address}")?;
+            return Ok(());
+        }
+        Err(status) => return Err(control_error(address, status)),
+    }
+
+    // The daemon removes its pidfile only after it stopped serving
+    if let Address::Unix(socket) = address {
+        let pidfile = proto::pidfile_path(socket);
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while pidfile.exists() {
+            if Instant::now() > deadline {
+                return Err(user_error(format!(
+                    "Daemon at {address} did not exit, see {}",
+                    pidfile.display()
+                )));
+            }
+            std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+    }
+    writeln!(ui.status(), "Stopped daemon at {address}")?;
+    Ok(())
+}
+
+fn status(ui: &mut Ui, address: &Address) -> Result<(), CommandError> {
+    let pool = connect(address)?;
+    let reply =
+        pool.control(|mut control| async move { control.daemon_status(DaemonStatusReq {}).await });
+    let DaemonStatusReply {
+        pid,
+        version,
+        uptime_secs,
+        store_location,
+        ..
+    } = match reply {
+        Ok(reply) => reply.into_inner(),
+        Err(status) if is_unreachable(&status) => {
+            writeln!(ui.stdout(), "Daemon not running at {address}")?;
+            return Ok(());
+        }
+        Err(status) => return Err(control_error(address, status)),
+    };
+
+    let mounts = pool
+        .control(|mut control| async move { control.list_mounts(ListMountsReq {}).await })
+        .map_err(|status| control_error(address, status))?
+        .into_inner()
+        .mounts;
+    let stats = pool
+        .control(|mut control| async move { control.store_stats(StoreStatsReq::default()).await })
+        .map_err(|status| control_error(address, status))?
+        .into_inner();
+
+    let mut stdout = ui.stdout();
+    writeln!(stdout, "Daemon running at {address}")?;
+    writeln!(stdout, "PID: {pid}")?;
+    writeln!(stdout, "Version: {version}")?;
+    writeln!(stdout, "Uptime: {}", format_uptime(uptime_secs))?;
+    writeln!(stdout, "Store: {store_location}")?;
+    for (kind, objects) in [
+        ("commits", stats.commits),
+        ("trees", stats.trees),
+        ("files", stats.files),
+        ("symlinks", stats.symlinks),
+    ] {
+        let objects = objects.unwrap_or_default();
+        writeln!(
+            stdout,
+            "  {kind}: {} ({} bytes)",
+            objects.count, objects.bytes
+        )?;
+    }
+    writeln!(stdout, "  dedup ratio: {:.2}", stats.dedup_ratio)?;
+    // Only known to the daemon when it has an upstream configured
+    if let Ok(upstream) = pool.health("upstream") {
+        let upstream = match upstream {
+            ServingStatus::Serving => "serving",
+            ServingStatus::NotServing => "not serving",
+            _ => "unknown",
+        };
+        writeln!(stdout, "Upstream: {upstream}")?;
+    }
+    if mounts.is_empty() {
+        writeln!(stdout, "Mounts: none")?;
+    } else {
+        writeln!(stdout, "Mounts:")?;
+        for mount in mounts {
+            let workspace = match mount.workspace_id.as_str() {
+                "" => "uninitialized",
+                workspace_id => workspace_id,
+            };
+            let repo = match mount.repo_id.as_str() {
+                "" => "default",
+                repo_id => repo_id,
+            };
+            writeln!(
+                stdout,
+                "  {} ({workspace}, repo {repo}) at tree {}",
+                mount.path,
+                TreeId::new(mount.tree_id).hex()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn connect(address: &Address) -> Result<ClientPool, CommandError> {
+    ClientPool::connect_once(address).map_err(user_error)
+}
+
+/// Daemons only serve `Control` on their Unix socket, never over TCP.
+pub fn control_error(address: &Address, status: Status) -> CommandError {
+    if status.code() == Code::Unimplemented {
+        user_error(format!(
+            "The daemon at {address} can only be controlled through its local socket"
+        ))
+    } else {
+        user_error(status)
+    }
+}
+
+pub fn is_unreachable(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded)
+}
+
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m {secs}s")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m {secs}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {secs}s")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime() {
+        assert_eq!(format_uptime(0), "0s");
+        assert_eq!(format_uptime(61), "1m 1s");
+        assert_eq!(format_uptime(3600), "1h 0m 0s");
+        assert_eq!(
+            format_uptime(2 * 86400 + 3 * 3600 + 4 * 60 + 5),
+            "2d 3h 4m 5s"
+        );
+    }
+}
@@ -1,6 +1,8 @@
+use std::io::Write;
+
 use jj_cli::{
     cli_util::{CliRunner, CommandHelper},
-    command_error::CommandError,
+    command_error::{user_error, user_error_with_hint, CommandError},
     ui::Ui,
 };
 use jj_lib::{
@@ -10,8 +12,13 @@ use jj_lib::{
     workspace::{WorkingCopyFactories, Workspace, WorkspaceInitError},
 };
 
+mod autostart;
 mod backend;
 mod blocking_client;
+mod client;
+mod daemon;
+mod status;
+mod unmount;
 mod working_copy;
 
 use backend::CultivateBackend;
@@ -21,7 +28,28 @@ use working_copy::{CultivateWorkingCopy, CultivateWorkingCopyFactory};
 #[derive(Debug, Clone, clap::Subcommand)]
 enum CultivateCommands {
     Init,
-    Status,
+    /// Show the daemon and mount state of this workspace
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Stop the daemon from serving a working copy
+    Unmount {
+        /// The working copy to unmount, this workspace by default
+        path: Option<std::path::PathBuf>,
+        /// Keep the mount's state, so mounting the path again restores it
+        #[arg(long)]
+        keep_state: bool,
+        /// Unmount even with changes since the last snapshot
+        #[arg(long)]
+        force: bool,
+    },
+    /// Manage the local cultivate daemon
+    Daemon {
+        #[command(subcommand)]
+        command: daemon::DaemonCommand,
+    },
 }
 
 #[derive(Debug, Clone, clap::Args)]
@@ -45,9 +73,7 @@ fn create_store_factories() -> StoreFactories {
     store_factories.add_backend(
         "cultivate",
         Box::new(|settings, store_path| {
-            Ok(Box::new(
-                CultivateBackend::new(settings, store_path).unwrap(),
-            ))
+            Ok(Box::new(CultivateBackend::load(settings, store_path)?))
         }),
     );
     store_factories
@@ -58,35 +84,56 @@ pub fn default_working_copy_factory() -> Box<dyn WorkingCopyFactory> {
 }
 
 fn run_cultivate_command(
-    _ui: &mut Ui,
+    ui: &mut Ui,
     command_helper: &CommandHelper,
     command: CultivateSubcommand,
 ) -> Result<(), CommandError> {
     let CultivateSubcommand::Cultivate(CultivateArgs { command }) = command;
     match command {
-        CultivateCommands::Status => todo!(),
+        CultivateCommands::Status { json } => status::run(ui, command_helper, json),
+        CultivateCommands::Unmount {
+            path,
+            keep_state,
+            force,
+        } => unmount::run(ui, command_helper, path, keep_state, force),
+        CultivateCommands::Daemon { command } => {
+            daemon::run(ui, command_helper.settings(), command)
+        }
         CultivateCommands::Init => {
             let wc_path = command_helper.cwd();
 
             // NOTE: We need to tell the daemon to mount the filesystem BEFORE we
             // initalize the core jj internals or we'll have writes on-disk and on
             // vfs.
-            let client = crate::blocking_client::BlockingJujutsuInterfaceClient::connect(
-                "http://[::1]:10000",
-            )
-            .unwrap();
-            client
+            let path = wc_path
+                .to_str()
+                .ok_or_else(|| user_error("Working copy path is not valid UTF-8"))?
+                .to_string();
+            // An existing workspace gets its mount back rather than a new one
+            let reattach = wc_path.join(".jj").is_dir();
+            let address = client::daemon_address(command_helper.settings());
+            autostart::ensure_running(command_helper.settings(), &address)?;
+            let client = crate::blocking_client::BlockingJujutsuInterfaceClient::connect(&address)
+                .map_err(user_error)?;
+            let repo_id = client
                 .initialize(proto::jj_interface::InitializeReq {
-                    path: wc_path.as_os_str().to_str().unwrap().to_string(),
+                    path: path.clone(),
+                    reattach,
                 })
-                .unwrap();
+                .map_err(initialize_error)?
+                .into_inner()
+                .repo_id;
+            if reattach {
+                writeln!(ui.status(), "Reattached to the mount of {path}")?;
+                return Ok(());
+            }
 
             assert!(std::env::set_current_dir(wc_path).is_ok());
             Workspace::init_with_factories(
                 command_helper.settings(),
                 wc_path,
                 &|settings, store_path| {
-                    let backend = CultivateBackend::new(settings, store_path)?;
+                    let backend = CultivateBackend::init(settings, store_path, &repo_id)?;
                     Ok(Box::new(backend))
                 },
                 Signer::from_settings(command_helper.settings())
@@ -99,12 +146,25 @@ fn run_cultivate_command(
                 // &*default_working_copy_factory(),
                 WorkspaceId::default(),
             )?;
-            assert!(std::env::set_current_dir(&wc_path).is_ok());
+            assert!(std::env::set_current_dir(wc_path).is_ok());
             Ok(())
         }
     }
 }
 
+fn initialize_error(status: tonic::Status) -> CommandError {
+    match status.code() {
+        tonic::Code::AlreadyExists => user_error_with_hint(
+            status.message().to_string(),
+            "Run `jj cultivate unmount` to start over",
+        ),
+        tonic::Code::InvalidArgument | tonic::Code::FailedPrecondition | tonic::Code::Internal => {
+            user_error(status.message().to_string())
+        }
+        _ => user_error(status),
+    }
+}
+
 fn main() -> std::process::ExitCode {
     let mut working_copy_factories = WorkingCopyFactories::new();
     working_copy_factories.insert(
@@ -0,0 +1,124 @@
+use std::io::Write;
+
+use jj_cli::{
+    cli_util::CommandHelper,
+    command_error::{user_error, CommandError},
+    ui::Ui,
+};
+use jj_lib::{backend::TreeId, object_id::ObjectId, op_store::OperationId};
+use proto::jj_interface::{GetCheckoutStateReq, GetTreeStateReq};
+use serde::Serialize;
+use tonic::Code;
+
+use crate::{backend, client, daemon};
+
+/// What the daemon knows about a workspace.
+#[derive(Debug, Default, Serialize)]
+struct WorkspaceStatus {
+    /// The daemon this workspace talks to.
+    daemon: String,
+    daemon_running: bool,
+    /// Whether the daemon serves this workspace.
+    mounted: bool,
+    tree_id: Option<String>,
+    op_id: Option<String>,
+    workspace_id: Option<String>,
+    /// Paths written to since the last snapshot. Always `None` (`null` in
+    /// JSON) for now, as the daemon has no write path to track them on.
+    modified_paths: Option<u64>,
+}
+
+pub fn run(ui: &mut Ui, command_helper: &CommandHelper, json: bool) -> Result<(), CommandError> {
+    let workspace_loader = command_helper.workspace_loader()?;
+    let workspace_root = workspace_loader.workspace_root();
+    let working_copy_path = workspace_root
+        .to_str()
+        .ok_or_else(|| user_error("Workspace path is not valid UTF-8"))?
+        .to_string();
+    let address = client::daemon_address(command_helper.settings());
+    let mut status = WorkspaceStatus {
+        daemon: address.to_string(),
+        ..Default::default()
+    };
+
+    let mut pool = daemon::connect(&address)?;
+    if let Some(repo_id) = backend::read_repo_id(&workspace_loader.repo_path().join("store"))? {
+        pool = pool.with_repo_id(&repo_id).map_err(user_error)?;
+    }
+    let tree_state = pool.block_on(|mut client| {
+        let working_copy_path = working_copy_path.clone();
+        async move {
+            client
+                .get_tree_state(GetTreeStateReq { working_copy_path })
+                .await
+        }
+    });
+    match tree_state {
+        Ok(reply) => {
+            let reply = reply.into_inner();
+            status.daemon_running = true;
+            status.mounted = true;
+            status.tree_id = Some(TreeId::new(reply.tree_id).hex());
+        }
+        Err(err) if daemon::is_unreachable(&err) => {}
+        Err(err) if err.code() == Code::NotFound => status.daemon_running = true,
+        Err(err) => return Err(user_error(err)),
+    }
+
+    if status.mounted {
+        let checkout_state = pool.block_on(|mut client| {
+            let working_copy_path = working_copy_path.clone();
+            async move {
+                client
+                    .get_checkout_state(GetCheckoutStateReq { working_copy_path })
+                    .await
+            }
+        });
+        match checkout_state {
+            Ok(reply) => {
+                let reply = reply.into_inner();
+                status.op_id = Some(OperationId::new(reply.op_id).hex());
+                status.workspace_id = Some(String::from_utf8_lossy(&reply.workspace_id).into());
+            }
+            // Not checked out yet
+            Err(err) if err.code() == Code::FailedPrecondition => {}
+            Err(err) => return Err(user_error(err)),
+        }
+    }
+
+    let mut stdout = ui.stdout();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &status).map_err(user_error)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+    let running = if status.daemon_running {
+        "running"
+    } else {
+        "not running"
+    };
+    writeln!(stdout, "Daemon: {} ({running})", status.daemon)?;
+    if !status.mounted {
+        writeln!(stdout, "Mount: {working_copy_path} is not mounted")?;
+        return Ok(());
+    }
+    writeln!(stdout, "Mount: {working_copy_path} is live")?;
+    let unknown = || "unknown".to_string();
+    writeln!(stdout, "Tree: {}", status.tree_id.unwrap_or_else(unknown))?;
+    writeln!(
+        stdout,
+        "Operation: {}",
+        status.op_id.unwrap_or_else(unknown)
+    )?;
+    writeln!(
+        stdout,
+        "Workspace: {}",
+        status.workspace_id.unwrap_or_else(unknown)
+    )?;
+    let modified_paths = status.modified_paths.map_or_else(
+        || "unavailable (not tracked by the daemon yet)".to_string(),
+        |count| count.to_string(),
+    );
+    writeln!(stdout, "Modified paths: {modified_paths}")?;
+    Ok(())
+}
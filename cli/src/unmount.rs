@@ -0,0 +1,73 @@
+use std::{io::Write, path::PathBuf};
+
+use jj_cli::{
+    cli_util::CommandHelper,
+    command_error::{user_error, user_error_with_hint, CommandError},
+    ui::Ui,
+};
+use proto::control::UnmountReq;
+use tonic::Code;
+
+use crate::{client, daemon};
+
+pub fn run(
+    ui: &mut Ui,
+    command_helper: &CommandHelper,
+    path: Option<PathBuf>,
+    keep_state: bool,
+    force: bool,
+) -> Result<(), CommandError> {
+    let path = match path {
+        // Mounts are registered by canonical path, as `init` sees the working
+        // copy. One whose directory is gone can only be named as registered.
+        Some(path) => {
+            let path = command_helper.cwd().join(path);
+            path.canonicalize().unwrap_or(path)
+        }
+        None => command_helper
+            .workspace_loader()?
+            .workspace_root()
+            .to_path_buf(),
+    };
+    let path = path
+        .to_str()
+        .ok_or_else(|| user_error("Working copy path is not valid UTF-8"))?
+        .to_string();
+    let address = client::daemon_address(command_helper.settings());
+
+    let reply = daemon::connect(&address)?.control(|mut control| {
+        let path = path.clone();
+        async move {
+            control
+                .unmount(UnmountReq {
+                    path,
+                    keep_state,
+                    force,
+                })
+                .await
+        }
+    });
+    match reply {
+        Ok(_) => {}
+        Err(status) if daemon::is_unreachable(&status) => {
+            return Err(user_error(format!("Daemon not running at {address}")));
+        }
+        Err(status) if status.code() == Code::FailedPrecondition => {
+            return Err(user_error_with_hint(
+                status.message().to_string(),
+                "Pass --force to unmount anyway",
+            ));
+        }
+        Err(status) if status.code() == Code::NotFound => {
+            return Err(user_error(status.message().to_string()));
+        }
+        Err(status) => return Err(daemon::control_error(&address, status)),
+    }
+
+    if keep_state {
+        writeln!(ui.status(), "Unmounted {path}, keeping its state")?;
+    } else {
+        writeln!(ui.status(), "Unmounted {path}")?;
+    }
+    Ok(())
+}
@@ -21,7 +21,7 @@ use jj_lib::{
 use proto::jj_interface::{GetCheckoutStateReq, GetTreeStateReq, SnapshotReq};
 use tracing::{info, warn};
 
-use crate::blocking_client::BlockingJujutsuInterfaceClient;
+use crate::{backend::CultivateBackend, blocking_client::BlockingJujutsuInterfaceClient};
 
 pub struct CultivateWorkingCopyFactory {}
 
@@ -51,7 +51,7 @@ impl WorkingCopyFactory for CultivateWorkingCopyFactory {
         Ok(Box::new(CultivateWorkingCopy::load(
             store,
             working_copy_path,
-        )))
+        )?))
     }
 }
 
@@ -59,8 +59,8 @@ pub struct CultivateWorkingCopy {
     store: Arc<Store>,
     working_copy_path: PathBuf,
     client: BlockingJujutsuInterfaceClient,
-    /// Only access through get_checkout_state
-    checkout_state: OnceCell<CheckoutState>,
+    checkout_state: CheckoutState,
+    /// Only access through get_tree_state
     tree_state: OnceCell<TreeState>,
 }
 
@@ -75,7 +75,7 @@ impl CultivateWorkingCopy {
         operation_id: OperationId,
         workspace_id: WorkspaceId,
     ) -> Result<Self, WorkingCopyStateError> {
-        let client = BlockingJujutsuInterfaceClient::connect("http://[::1]:10000").unwrap();
+        let client = backend_client(&store)?;
         client
             .set_checkout_state(proto::jj_interface::SetCheckoutStateReq {
                 working_copy_path: working_copy_path.to_str().unwrap().to_string(),
@@ -84,28 +84,89 @@ impl CultivateWorkingCopy {
                     workspace_id: workspace_id.as_str().into(),
                 }),
             })
-            .unwrap();
+            .map_err(|status| daemon_state_error(&working_copy_path, status))?;
         Ok(CultivateWorkingCopy {
             store,
             working_copy_path,
             client,
-            checkout_state: OnceCell::new(),
+            checkout_state: CheckoutState {
+                operation_id,
+                workspace_id,
+            },
             tree_state: OnceCell::new(),
         })
     }
 
-    fn load(store: Arc<Store>, working_copy_path: PathBuf) -> Self {
-        let client = BlockingJujutsuInterfaceClient::connect("http://[::1]:10000").unwrap();
-        CultivateWorkingCopy {
+    fn load(store: Arc<Store>, working_copy_path: PathBuf) -> Result<Self, WorkingCopyStateError> {
+        let client = backend_client(&store)?;
+        let checkout_state = get_checkout_state(&client, &working_copy_path)?;
+        Ok(CultivateWorkingCopy {
             store,
             working_copy_path,
             client,
-            checkout_state: OnceCell::new(),
+            checkout_state,
             tree_state: OnceCell::new(),
-        }
+        })
+    }
+}
+
+/// The working copy talks to the same daemon as the backend it is paired with.
+fn backend_client(store: &Store) -> Result<BlockingJujutsuInterfaceClient, WorkingCopyStateError> {
+    let backend = store
+        .backend_impl()
+        .downcast_ref::<CultivateBackend>()
+        .ok_or_else(|| WorkingCopyStateError {
+            message: "The cultivate working copy requires the cultivate backend".to_string(),
+            err: "unsupported backend".into(),
+        })?;
+    Ok(backend.client().clone())
+}
+
+/// The daemon answers `NOT_FOUND` for working copies it does not serve, e.g.
+/// ones it detached on restart because their tree was lost with the rest of
+/// its in-memory store.
+fn daemon_state_error(working_copy_path: &Path, status: tonic::Status) -> WorkingCopyStateError {
+    let message = if status.code() == tonic::Code::NotFound {
+        format!(
+            "The daemon does not serve the working copy at {}",
+            working_copy_path.display()
+        )
+    } else {
+        format!(
+            "Failed to get the state of the working copy at {} from the daemon",
+            working_copy_path.display()
+        )
+    };
+    WorkingCopyStateError {
+        message,
+        err: status.message().into(),
     }
 }
 
+fn get_checkout_state(
+    client: &BlockingJujutsuInterfaceClient,
+    working_copy_path: &Path,
+) -> Result<CheckoutState, WorkingCopyStateError> {
+    let checkout_state = client
+        .get_checkout_state(GetCheckoutStateReq {
+            working_copy_path: working_copy_path.to_str().unwrap().to_string(),
+        })
+        .map_err(|status| daemon_state_error(working_copy_path, status))?
+        .into_inner();
+    let workspace_id =
+        String::from_utf8(checkout_state.workspace_id).map_err(|err| WorkingCopyStateError {
+            message: format!(
+                "The daemon returned an invalid workspace id for the working copy at {}",
+                working_copy_path.display()
+            ),
+            err: err.into(),
+        })?;
+    Ok(CheckoutState {
+        operation_id: OperationId::new(checkout_state.op_id),
+        workspace_id: WorkspaceId::new(workspace_id),
+    })
+}
+
 /// Working copy state stored in "checkout" file.
 #[derive(Clone, Debug)]
 struct CheckoutState {
@@ -124,60 +185,47 @@ impl TreeState {
 }
 
 impl CultivateWorkingCopy {
-    fn get_tree_state<'a>(&'a self) -> &'a TreeState {
-        self.tree_state.get_or_init(|| {
-            let tree_state = self
-                .client
-                .get_tree_state(GetTreeStateReq {
-                    working_copy_path: self.working_copy_path.to_str().unwrap().to_string(),
-                })
-                .unwrap()
-                .into_inner();
-            let tree_ids_builder: MergeBuilder<TreeId> =
-                MergeBuilder::from_iter([TreeId::new(tree_state.tree_id)]);
-            TreeState {
-                tree_id: MergedTreeId::Merge(tree_ids_builder.build()),
-            }
-        })
-    }
-
-    fn get_checkout_state<'a>(&'a self) -> &'a CheckoutState {
-        self.checkout_state.get_or_init(|| {
-            let checkout_state = self
-                .client
-                .get_checkout_state(GetCheckoutStateReq {
-                    working_copy_path: self.working_copy_path.to_str().unwrap().to_string(),
-                })
-                .unwrap()
-                .into_inner();
-            CheckoutState {
-                operation_id: OperationId::new(checkout_state.op_id),
-                workspace_id: WorkspaceId::new(
-                    std::str::from_utf8(&checkout_state.workspace_id)
-                        .unwrap()
-                        .to_string(),
-                ),
-            }
-        })
+    fn get_tree_state(&self) -> Result<&TreeState, WorkingCopyStateError> {
+        if let Some(tree_state) = self.tree_state.get() {
+            return Ok(tree_state);
+        }
+        let tree_state = self
+            .client
+            .get_tree_state(GetTreeStateReq {
+                working_copy_path: self.working_copy_path.to_str().unwrap().to_string(),
+            })
+            .map_err(|status| daemon_state_error(&self.working_copy_path, status))?
+            .into_inner();
+        let tree_ids_builder: MergeBuilder<TreeId> =
+            MergeBuilder::from_iter([TreeId::new(tree_state.tree_id)]);
+        Ok(self.tree_state.get_or_init(|| TreeState {
+            tree_id: MergedTreeId::Merge(tree_ids_builder.build()),
+        }))
     }
 
     fn get_working_copy_lock(&self) -> DaemonLock {
         DaemonLock::new()
     }
 
-    fn snapshot(&mut self, _options: SnapshotOptions) -> TreeState {
+    fn snapshot(&mut self, _options: SnapshotOptions) -> Result<TreeState, SnapshotError> {
         let tree_state = self
             .client
             .snapshot(SnapshotReq {
                 working_copy_path: self.working_copy_path.to_str().unwrap().to_string(),
             })
-            .unwrap()
+            .map_err(|status| {
+                let err = daemon_state_error(&self.working_copy_path, status);
+                SnapshotError::Other {
+                    message: err.message,
+                    err: err.err,
+                }
+            })?
             .into_inner();
         let tree_ids_builder: MergeBuilder<TreeId> =
             MergeBuilder::from_iter([TreeId::new(tree_state.tree_id)]);
-        TreeState {
+        Ok(TreeState {
             tree_id: MergedTreeId::Merge(tree_ids_builder.build()),
-        }
+        })
     }
 }
 
@@ -205,15 +253,15 @@ impl WorkingCopy for CultivateWorkingCopy {
     }
 
     fn workspace_id(&self) -> &WorkspaceId {
-        &self.get_checkout_state().workspace_id
+        &self.checkout_state.workspace_id
     }
 
     fn operation_id(&self) -> &OperationId {
-        &self.get_checkout_state().operation_id
+        &self.checkout_state.operation_id
     }
 
     fn tree_id(&self) -> Result<&MergedTreeId, WorkingCopyStateError> {
-        Ok(self.get_tree_state().current_tree_id())
+        Ok(self.get_tree_state()?.current_tree_id())
     }
 
     fn sparse_patterns(&self) -> Result<&[RepoPathBuf], WorkingCopyStateError> {
@@ -227,7 +275,7 @@ impl WorkingCopy for CultivateWorkingCopy {
             client: self.client.clone(),
             store: self.store.clone(),
             working_copy_path: self.working_copy_path.clone(),
-            checkout_state: OnceCell::new(),
+            checkout_state: get_checkout_state(&self.client, &self.working_copy_path)?,
             tree_state: OnceCell::new(),
         };
         let old_operation_id = wc.operation_id().clone();
@@ -271,7 +319,7 @@ impl LockedWorkingCopy for LockedCultivateWorkingCopy {
     }
 
     fn snapshot(&mut self, options: SnapshotOptions) -> Result<MergedTreeId, SnapshotError> {
-        let tree_state = self.wc.snapshot(options);
+        let tree_state = self.wc.snapshot(options)?;
         Ok(tree_state.tree_id)
     }
 
@@ -0,0 +1,389 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use proto::Address;
+use serde::Deserialize;
+use tonic::transport::Uri;
+
+/// Command-line flags, which override the config file and the environment.
+#[derive(Debug, Default, clap::Parser)]
+#[command(about = "The cultivate daemon")]
+pub struct Args {
+    /// Config file to read, `$XDG_CONFIG_HOME/cultivate/daemon.toml` by
+    /// default
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Address to serve on, e.g. `unix:/run/cultivate.sock` or
+    /// `http://[::1]:10000`
+    #[arg(long)]
+    listen: Option<String>,
+    /// Address to serve Prometheus metrics on, e.g. `127.0.0.1:9090`
+    #[arg(long)]
+    metrics_listen: Option<SocketAddr>,
+    /// Concurrency to report to clients instead of the store's own estimate
+    #[arg(long)]
+    concurrency: Option<usize>,
+    /// Server to check the health of, e.g. `http://build-cache:10000`
+    #[arg(long)]
+    upstream: Option<String>,
+    /// Where state that outlives the daemon is kept
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+    /// Directory of the persistent store tiers. Ignored, with a warning, until
+    /// the store has one
+    #[arg(long)]
+    store_dir: Option<PathBuf>,
+    /// Store tiers from fastest to slowest, repeated for each tier
+    #[arg(long = "store-tier", value_enum)]
+    store_tiers: Vec<Tier>,
+    /// Give every new repository a store of its own instead of sharing one
+    #[arg(long)]
+    per_repo_stores: bool,
+    /// Bytes of objects to cache in memory in front of slower tiers. Ignored,
+    /// with a warning, until the store has one
+    #[arg(long)]
+    cache_bytes: Option<u64>,
+    /// Leave the working copies mounted by the last daemon unmounted, to be
+    /// reattached on demand
+    #[arg(long)]
+    no_remount: bool,
+    /// Most verbose level to log: error, warn, info, debug or trace
+    #[arg(long)]
+    log_level: Option<tracing::Level>,
+    /// How log lines are laid out
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+}
+
+/// A layer of the store. Only the in-memory one exists so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    Memory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Compact,
+    Full,
+    Pretty,
+}
+
+/// The config file. Every value is optional and falls back to its default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    server: ServerSection,
+    store: StoreSection,
+    cache: CacheSection,
+    mounts: MountsSection,
+    log: LogSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    listen: Option<String>,
+    metrics_listen: Option<SocketAddr>,
+    concurrency: Option<usize>,
+    upstream: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StoreSection {
+    dir: Option<PathBuf>,
+    tiers: Option<Vec<Tier>>,
+    per_repo: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CacheSection {
+    bytes: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MountsSection {
+    state_dir: Option<PathBuf>,
+    remount: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LogSection {
+    level: Option<String>,
+    format: Option<LogFormat>,
+}
+
+/// Daemon configuration: defaults, overridden by the config file, then the
+/// environment, then command-line flags.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address to serve on, in the same form clients use to connect.
+    pub address: Address,
+    /// Overrides the concurrency the store would report to clients.
+    pub concurrency: Option<usize>,
+    /// Where to serve Prometheus metrics, if anywhere.
+    pub metrics_address: Option<SocketAddr>,
+    /// Server whose health is reported as the `upstream` service.
+    pub upstream: Option<String>,
+    /// Where state that outlives the daemon, like the mount registry, is kept.
+    pub state_dir: PathBuf,
+    /// Directory of the persistent store tiers. Unused until there are any.
+    pub store_dir: Option<PathBuf>,
+    /// Store tiers from fastest to slowest.
+    pub store_tiers: Vec<Tier>,
+    /// Whether each repository gets a store of its own.
+    pub per_repo_stores: bool,
+    /// Bytes of objects to cache in memory in front of slower tiers. Unused
+    /// until there are any.
+    pub cache_bytes: Option<u64>,
+    /// Whether to remount the working copies registered by the last daemon.
+    pub remount: bool,
+    pub log_level: tracing::Level,
+    pub log_format: LogFormat,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: proto::default_address(),
+            concurrency: None,
+            metrics_address: None,
+            upstream: None,
+            state_dir: default_state_dir(),
+            store_dir: None,
+            store_tiers: vec![Tier::Memory],
+            per_repo_stores: false,
+            cache_bytes: None,
+            remount: true,
+            log_level: tracing::Level::INFO,
+            log_format: LogFormat::Compact,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(args: Args) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        match &args.config {
+            Some(path) => config.apply_file(&read_file(path)?)?,
+            None => {
+                let path = default_config_path();
+                if path.exists() {
+                    config.apply_file(&read_file(&path)?)?;
+                }
+            }
+        }
+        config.apply_env()?;
+        config.apply_args(args);
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, file: &ConfigFile) -> anyhow::Result<()> {
+        let ConfigFile {
+            server,
+            store,
+            cache,
+            mounts,
+            log,
+        } = file;
+        if let Some(listen) = &server.listen {
+            self.address = listen.as_str().into();
+        }
+        self.metrics_address = server.metrics_listen.or(self.metrics_address);
+        self.concurrency = server.concurrency.or(self.concurrency);
+        self.upstream = server.upstream.clone().or(self.upstream.take());
+        self.store_dir = store.dir.clone().or(self.store_dir.take());
+        if let Some(tiers) = &store.tiers {
+            self.store_tiers = tiers.clone();
+        }
+        self.per_repo_stores = store.per_repo.unwrap_or(self.per_repo_stores);
+        self.cache_bytes = cache.bytes.or(self.cache_bytes);
+        if let Some(state_dir) = &mounts.state_dir {
+            self.state_dir = state_dir.clone();
+        }
+        self.remount = mounts.remount.unwrap_or(self.remount);
+        if let Some(level) = &log.level {
+            self.log_level = level
+                .parse()
+                .map_err(|_| anyhow!("Invalid log level `{level}`"))?;
+        }
+        self.log_format = log.format.unwrap_or(self.log_format);
+        Ok(())
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Ok(address) = std::env::var(proto::ADDRESS_ENV) {
+            self.address = address.as_str().into();
+        }
+        if let Ok(concurrency) = std::env::var("CULTIVATE_CONCURRENCY") {
+            self.concurrency = Some(concurrency.parse()?);
+        }
+        if let Ok(port) = std::env::var("CULTIVATE_METRICS_PORT") {
+            self.metrics_address = Some(SocketAddr::from((Ipv4Addr::LOCALHOST, port.parse()?)));
+        }
+        if let Some(state_dir) = std::env::var_os("CULTIVATE_STATE_DIR") {
+            self.state_dir = state_dir.into();
+        }
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: Args) {
+        if let Some(listen) = args.listen {
+            self.address = listen.as_str().into();
+        }
+        self.metrics_address = args.metrics_listen.or(self.metrics_address);
+        self.concurrency = args.concurrency.or(self.concurrency);
+        self.upstream = args.upstream.or(self.upstream.take());
+        self.state_dir = args.state_dir.unwrap_or(self.state_dir.clone());
+        self.store_dir = args.store_dir.or(self.store_dir.take());
+        if !args.store_tiers.is_empty() {
+            self.store_tiers = args.store_tiers;
+        }
+        if args.per_repo_stores {
+            self.per_repo_stores = true;
+        }
+        self.cache_bytes = args.cache_bytes.or(self.cache_bytes);
+        if args.no_remount {
+            self.remount = false;
+        }
+        self.log_level = args.log_level.unwrap_or(self.log_level);
+        self.log_format = args.log_format.unwrap_or(self.log_format);
+    }
+}
+
+fn read_file(path: &Path) -> anyhow::Result<ConfigFile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+}
+
+/// `$XDG_CONFIG_HOME/cultivate/daemon.toml`, falling back to
+/// `~/.config/cultivate/daemon.toml`.
+fn default_config_path() -> PathBuf {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .unwrap_or_else(std::env::temp_dir);
+    config_home.join("cultivate").join("daemon.toml")
+}
+
+/// `$XDG_STATE_HOME/cultivate`, falling back to `~/.local/state/cultivate`.
+fn default_state_dir() -> PathBuf {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .unwrap_or_else(std::env::temp_dir);
+    state_home.join("cultivate")
+}
+
+/// The socket to listen on for an `Address::Tcp` URI.
+pub fn tcp_listen_addr(address: &str) -> anyhow::Result<SocketAddr> {
+    let uri: Uri = address
+        .parse()
+        .with_context(|| format!("Invalid address `{address}`"))?;
+    let authority = uri
+        .authority()
+        .ok_or_else(|| anyhow!("Address `{address}` has no host"))?;
+    authority
+        .as_str()
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("Address `{address}` did not resolve"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_then_flags() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            [server]
+            listen = "http://[::1]:10000"
+            metrics_listen = "127.0.0.1:9090"
+            upstream = "http://cache:10000"
+
+            [store]
+            tiers = ["memory"]
+            per_repo = true
+
+            [cache]
+            bytes = 1024
+
+            [mounts]
+            remount = false
+
+            [log]
+            level = "debug"
+            format = "pretty"
+            "#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.apply_file(&file).unwrap();
+        assert_eq!(config.address, Address::Tcp("http://[::1]:10000".into()));
+        assert_eq!(
+            config.metrics_address,
+            Some("127.0.0.1:9090".parse().unwrap())
+        );
+        assert_eq!(config.upstream.as_deref(), Some("http://cache:10000"));
+        assert_eq!(config.cache_bytes, Some(1024));
+        assert!(config.per_repo_stores);
+        assert!(!config.remount);
+        assert_eq!(config.log_level, tracing::Level::DEBUG);
+        assert_eq!(config.log_format, LogFormat::Pretty);
+
+        let args = <Args as clap::Parser>::try_parse_from([
+            "daemon",
+            "--listen",
+            "unix:/tmp/yak.sock",
+            "--log-format",
+            "full",
+            "--cache-bytes",
+            "2048",
+        ])
+        .unwrap();
+        config.apply_args(args);
+        assert_eq!(config.address, Address::Unix("/tmp/yak.sock".into()));
+        assert_eq!(config.log_format, LogFormat::Full);
+        assert_eq!(config.cache_bytes, Some(2048));
+        // Untouched by the flags
+        assert_eq!(config.log_level, tracing::Level::DEBUG);
+        assert!(!config.remount);
+    }
+
+    #[test]
+    fn invalid_file() {
+        assert!(toml::from_str::<ConfigFile>("[server]\nlisten = 3").is_err());
+        assert!(toml::from_str::<ConfigFile>("[store]\ntiers = [\"tape\"]").is_err());
+        assert!(toml::from_str::<ConfigFile>("[yak]\nshaved = true").is_err());
+        let file: ConfigFile = toml::from_str("[log]\nlevel = \"loud\"").unwrap();
+        assert!(Config::default().apply_file(&file).is_err());
+    }
+
+    #[test]
+    fn listen_addr() {
+        assert_eq!(
+            tcp_listen_addr("http://[::1]:10000").unwrap(),
+            "[::1]:10000".parse().unwrap()
+        );
+        // Clients connect to a bare address the same way
+        let Address::Tcp(uri) = Address::from("127.0.0.1:4000") else {
+            panic!("not a TCP address");
+        };
+        assert_eq!(
+            tcp_listen_addr(&uri).unwrap(),
+            "127.0.0.1:4000".parse().unwrap()
+        );
+        assert!(tcp_listen_addr("http://").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use std::{sync::Arc, time::Instant};
+
+use proto::control::*;
+use tokio::sync::Notify;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::{health::Health, metrics::Metrics, mounts::Mounts, repos::Repos, store};
+
+#[derive(Debug)]
+pub struct ControlService {
+    repos: Repos,
+    mounts: Mounts,
+    health: Health,
+    metrics: Metrics,
+    started: Instant,
+    /// Notified once the daemon should stop serving.
+    shutdown: Arc<Notify>,
+}
+
+impl ControlService {
+    pub fn new(
+        repos: Repos,
+        mounts: Mounts,
+        health: Health,
+        metrics: Metrics,
+        shutdown: Arc<Notify>,
+    ) -> control_server::ControlServer<Self> {
+        control_server::ControlServer::new(ControlService {
+            repos,
+            mounts,
+            health,
+            metrics,
+            started: Instant::now(),
+            shutdown,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl control_server::Control for ControlService {
+    #[tracing::instrument(skip(self))]
+    async fn daemon_status(
+        &self,
+        _request: Request<DaemonStatusReq>,
+    ) -> Result<Response<DaemonStatusReply>, Status> {
+        Ok(Response::new(DaemonStatusReply {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            store_location: "memory".to_string(),
+            mounts: self.mounts.paths(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn shutdown(
+        &self,
+        _request: Request<ShutdownReq>,
+    ) -> Result<Response<ShutdownReply>, Status> {
+        info!("Shutdown requested");
+        // Mounts are only unmounted once the requests in flight are done
+        self.health.stopping();
+        self.shutdown.notify_one();
+        Ok(Response::new(ShutdownReply {}))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn unmount(
+        &self,
+        request: Request<UnmountReq>,
+    ) -> Result<Response<UnmountReply>, Status> {
+        let req = request.into_inner();
+        if self.mounts.get(&req.path).is_none() {
+            return Err(Status::not_found(format!("{} is not mounted", req.path)));
+        }
+        // Writes don't go through the daemon yet, so it has no way to know
+        // whether the working copy is clean.
+        if !req.force {
+            return Err(Status::failed_precondition(format!(
+                "Can't tell whether {} has changes since its last snapshot",
+                req.path
+            )));
+        }
+        self.mounts
+            .unmount(&req.path, req.keep_state)
+            .map_err(|err| Status::internal(format!("Failed to update the mount registry: {err}")))?
+            .ok_or_else(|| Status::not_found(format!("{} is not mounted", req.path)))?;
+        Ok(Response::new(UnmountReply {}))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn list_mounts(
+        &self,
+        _request: Request<ListMountsReq>,
+    ) -> Result<Response<ListMountsReply>, Status> {
+        let mounts = self
+            .mounts
+            .list()
+            .into_iter()
+            .map(|(path, mount)| MountInfo {
+                path,
+                workspace_id: mount
+                    .checkout_state
+                    .map(|state| String::from_utf8_lossy(&state.workspace_id).into())
+                    .unwrap_or_default(),
+                tree_id: mount.tree_id.to_vec(),
+                repo_id: mount.repo_id,
+            })
+            .collect();
+        Ok(Response::new(ListMountsReply { mounts }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn store_stats(
+        &self,
+        request: Request<StoreStatsReq>,
+    ) -> Result<Response<StoreStatsReply>, Status> {
+        let req = request.into_inner();
+        let stats = self
+            .repos
+            .store(&req.repo_id)
+            .ok_or_else(|| Status::not_found(format!("Unknown repo {}", req.repo_id)))?
+            .stats();
+        let object_stats = |stats: store::ObjectStats| {
+            Some(ObjectStats {
+                count: stats.count,
+                bytes: stats.bytes,
+            })
+        };
+        Ok(Response::new(StoreStatsReply {
+            commits: object_stats(stats.commits),
+            trees: object_stats(stats.trees),
+            files: object_stats(stats.files),
+            symlinks: object_stats(stats.symlinks),
+            written_bytes: stats.written_bytes,
+            dedup_ratio: stats.dedup_ratio(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn build_info(
+        &self,
+        _request: Request<BuildInfoReq>,
+    ) -> Result<Response<BuildInfoReply>, Status> {
+        let profile = if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        };
+        Ok(Response::new(BuildInfoReply {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            profile: profile.to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn metrics(
+        &self,
+        _request: Request<MetricsReq>,
+    ) -> Result<Response<MetricsReply>, Status> {
+        Ok(Response::new(MetricsReply {
+            text: self.metrics.render(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use control_server::Control;
+
+    use super::*;
+    use crate::repos::DEFAULT_REPO;
+
+    #[tokio::test]
+    async fn shutdown_stops_serving() {
+        let mounts = Mounts::default();
+        mounts.mount("/tmp/yak", DEFAULT_REPO, [0; 32]).unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let service = ControlService {
+            repos: Repos::default(),
+            mounts: mounts.clone(),
+            health: Health::new(Repos::default(), mounts.clone()),
+            metrics: Metrics::default(),
+            started: Instant::now(),
+            shutdown: shutdown.clone(),
+        };
+
+        let status = service
+            .daemon_status(Request::new(DaemonStatusReq {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.mounts, vec!["/tmp/yak".to_string()]);
+        assert_eq!(status.pid, std::process::id());
+
+        let listed = service
+            .list_mounts(Request::new(ListMountsReq {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .mounts;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].path, "/tmp/yak");
+        assert_eq!(listed[0].tree_id, vec![0; 32]);
+        assert_eq!(listed[0].repo_id, DEFAULT_REPO);
+
+        let unknown = service
+            .store_stats(Request::new(StoreStatsReq {
+                repo_id: "unknown".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(unknown.code(), tonic::Code::NotFound);
+
+        service
+            .shutdown(Request::new(ShutdownReq {}))
+            .await
+            .unwrap();
+        assert_eq!(
+            service.health.status("").await,
+            Some(proto::health::health_check_response::ServingStatus::NotServing)
+        );
+        // The permit is kept until the server waits for it
+        shutdown.notified().await;
+    }
+
+    #[tokio::test]
+    async fn unmount() {
+        let mounts = Mounts::default();
+        mounts.mount("/tmp/yak", DEFAULT_REPO, [0; 32]).unwrap();
+        let service = ControlService {
+            repos: Repos::default(),
+            mounts: mounts.clone(),
+            health: Health::new(Repos::default(), mounts.clone()),
+            metrics: Metrics::default(),
+            started: Instant::now(),
+            shutdown: Arc::new(Notify::new()),
+        };
+        let unmount = |path: &str, force| {
+            service.unmount(Request::new(UnmountReq {
+                path: path.to_string(),
+                keep_state: false,
+                force,
+            }))
+        };
+
+        let err = unmount("/tmp/shaved", true).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let err = unmount("/tmp/yak", false).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(mounts.paths(), vec!["/tmp/yak".to_string()]);
+        unmount("/tmp/yak", true).await.unwrap();
+        assert!(mounts.paths().is_empty());
+        let err = unmount("/tmp/yak", true).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use proto::health::{
+    health_check_response::ServingStatus, health_client::HealthClient, health_server,
+    HealthCheckRequest, HealthCheckResponse,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Endpoint, Request, Response, Status};
+
+use crate::{
+    mounts::Mounts,
+    repos::{Repos, DEFAULT_REPO},
+};
+
+/// How often `Watch` re-evaluates the status it streams.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the upstream server is checked, and how long it has to answer.
+const UPSTREAM_INTERVAL: Duration = Duration::from_secs(10);
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prefix of the per-mount service names, followed by the mount's path.
+const MOUNT_PREFIX: &str = "mount:";
+const UPSTREAM: &str = "upstream";
+
+/// The daemon's readiness, as reported through `grpc.health.v1.Health`.
+///
+/// Besides the overall status (the empty service name) and the gRPC services,
+/// there is a status for the store and one for each mount, named
+/// `mount:<path>`. The store is serving if it answers a read, a mount if its
+/// tree is in its repository's store. With an upstream server configured,
+/// `upstream` reports the overall status of that server.
+#[derive(Clone, Debug)]
+pub struct Health {
+    repos: Repos,
+    mounts: Mounts,
+    /// Whether `control.Control` is served, which it only is on Unix sockets.
+    control: bool,
+    stopping: Arc<AtomicBool>,
+    upstream: Option<Arc<Mutex<ServingStatus>>>,
+}
+
+impl Health {
+    pub fn new(repos: Repos, mounts: Mounts) -> Self {
+        Health {
+            repos,
+            mounts,
+            control: true,
+            stopping: Default::default(),
+            upstream: None,
+        }
+    }
+
+    /// Leaves `control.Control` out of the services reported.
+    pub fn without_control(mut self) -> Self {
+        self.control = false;
+        self
+    }
+
+    /// Checks the health of the server at `address` in the background.
+    pub fn with_upstream(mut self, address: &str) -> anyhow::Result<Self> {
+        let endpoint = Endpoint::from_shared(address.to_string())
+            .with_context(|| format!("Invalid upstream address `{address}`"))?
+            .connect_timeout(UPSTREAM_TIMEOUT)
+            .timeout(UPSTREAM_TIMEOUT);
+        let upstream = Arc::new(Mutex::new(ServingStatus::Unknown));
+        self.upstream = Some(upstream.clone());
+        tokio::spawn(async move {
+            loop {
+                let status = check_upstream(&endpoint).await;
+                *upstream.lock().unwrap() = status;
+                tokio::time::sleep(UPSTREAM_INTERVAL).await;
+            }
+        });
+        Ok(self)
+    }
+
+    /// Reports everything as not serving from now on.
+    pub fn stopping(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    /// The status of `service`, or `None` if there is no such service.
+    pub async fn status(&self, service: &str) -> Option<ServingStatus> {
+        let status = match service {
+            "" | "jj_interface.JujutsuInterface" => ServingStatus::Serving,
+            "control.Control" if self.control => ServingStatus::Serving,
+            "store" => serving(self.store_readable().await),
+            UPSTREAM => *self.upstream.as_ref()?.lock().unwrap(),
+            _ => {
+                let mount = self.mounts.get(service.strip_prefix(MOUNT_PREFIX)?)?;
+                serving(self.repos.has_tree(&mount.repo_id, mount.tree_id).await)
+            }
+        };
+        if self.stopping.load(Ordering::SeqCst) {
+            Some(ServingStatus::NotServing)
+        } else {
+            Some(status)
+        }
+    }
+
+    /// Whether the default store can read back the empty tree, which every
+    /// store holds.
+    async fn store_readable(&self) -> bool {
+        match self.repos.store(DEFAULT_REPO) {
+            Some(store) => store.has_tree(store.get_empty_tree_id()).await,
+            None => false,
+        }
+    }
+}
+
+fn serving(ok: bool) -> ServingStatus {
+    if ok {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    }
+}
+
+/// The overall status of the server at `endpoint`, `NotServing` if it cannot
+/// be reached.
+async fn check_upstream(endpoint: &Endpoint) -> ServingStatus {
+    let Ok(channel) = endpoint.connect().await else {
+        return ServingStatus::NotServing;
+    };
+    let request = HealthCheckRequest {
+        service: String::new(),
+    };
+    match HealthClient::new(channel).check(request).await {
+        Ok(reply) => reply.into_inner().status(),
+        Err(_) => ServingStatus::NotServing,
+    }
+}
+
+#[derive(Debug)]
+pub struct HealthService {
+    health: Health,
+}
+
+impl HealthService {
+    pub fn new(health: Health) -> health_server::HealthServer<Self> {
+        health_server::HealthServer::new(HealthService { health })
+    }
+}
+
+fn reply(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status.into(),
+    }
+}
+
+#[tonic::async_trait]
+impl health_server::Health for HealthService {
+    type WatchStream = ReceiverStream<Result<HealthCheckResponse, Status>>;
+
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        let status = self
+            .health
+            .status(&service)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Unknown service {service:?}")))?;
+        Ok(Response::new(reply(status)))
+    }
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let health = self.health.clone();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let status = health
+                    .status(&service)
+                    .await
+                    .unwrap_or(ServingStatus::ServiceUnknown);
+                if last != Some(status) {
+                    if tx.send(Ok(reply(status))).await.is_err() {
+                        // The client went away
+                        return;
+                    }
+                    last = Some(status);
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(WATCH_INTERVAL) => {}
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use health_server::Health as _;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+
+    async fn check(service: &HealthService, name: &str) -> Result<ServingStatus, tonic::Code> {
+        service
+            .check(Request::new(HealthCheckRequest {
+                service: name.to_string(),
+            }))
+            .await
+            .map(|reply| reply.into_inner().status())
+            .map_err(|status| status.code())
+    }
+
+    #[tokio::test]
+    async fn subsystem_status() {
+        let repos = Repos::default();
+        let mounts = Mounts::default();
+        let health = Health::new(repos.clone(), mounts.clone());
+        let service = HealthService {
+            health: health.clone(),
+        };
+
+        assert_eq!(check(&service, "").await, Ok(ServingStatus::Serving));
+        assert_eq!(check(&service, "store").await, Ok(ServingStatus::Serving));
+        assert_eq!(
+            check(&service, "control.Control").await,
+            Ok(ServingStatus::Serving)
+        );
+        let tcp = HealthService {
+            health: Health::new(repos.clone(), mounts.clone()).without_control(),
+        };
+        assert_eq!(
+            check(&tcp, "control.Control").await,
+            Err(tonic::Code::NotFound)
+        );
+        assert_eq!(
+            check(&service, "mount:/tmp/yak").await,
+            Err(tonic::Code::NotFound)
+        );
+        // The mount's tree is not in the store
+        mounts.mount("/tmp/yak", DEFAULT_REPO, [0; 32]).unwrap();
+        assert_eq!(
+            check(&service, "mount:/tmp/yak").await,
+            Ok(ServingStatus::NotServing)
+        );
+        let store = repos.store(DEFAULT_REPO).unwrap();
+        mounts
+            .mount("/tmp/shaved", DEFAULT_REPO, store.get_empty_tree_id())
+            .unwrap();
+        assert_eq!(
+            check(&service, "mount:/tmp/shaved").await,
+            Ok(ServingStatus::Serving)
+        );
+
+        let mut watch = service
+            .watch(Request::new(HealthCheckRequest {
+                service: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = watch.next().await.unwrap().unwrap();
+        assert_eq!(first.status(), ServingStatus::Serving);
+
+        assert_eq!(
+            check(&service, "upstream").await,
+            Err(tonic::Code::NotFound)
+        );
+
+        health.stopping();
+        assert_eq!(check(&service, "").await, Ok(ServingStatus::NotServing));
+        let next = watch.next().await.unwrap().unwrap();
+        assert_eq!(next.status(), ServingStatus::NotServing);
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream() {
+        // Nothing listens on port 1
+        let health = Health::new(Repos::default(), Mounts::default())
+            .with_upstream("http://127.0.0.1:1")
+            .unwrap();
+        let service = HealthService { health };
+        let mut status = check(&service, "upstream").await;
+        while status == Ok(ServingStatus::Unknown) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            status = check(&service, "upstream").await;
+        }
+        assert_eq!(status, Ok(ServingStatus::NotServing));
+        assert_eq!(check(&service, "").await, Ok(ServingStatus::Serving));
+        assert!(Health::new(Repos::default(), Mounts::default())
+            .with_upstream("not a uri")
+            .is_err());
+    }
+}
@@ -1,43 +1,155 @@
+use std::sync::Arc;
+
+use clap::Parser as _;
+use config::LogFormat;
+use proto::Address;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
+use tokio_stream::wrappers::UnixListenerStream;
 use tonic::transport::Server;
-use tracing::info;
+use tracing::{error, info, warn};
 
-mod store;
+mod config;
+mod control;
+mod health;
+mod metrics;
+mod mounts;
+mod pidfile;
+mod repos;
 mod service;
+mod socket;
+mod store;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let addr = "[::1]:10000".parse()?;
+    let config = config::Config::load(config::Args::parse())?;
 
     // fuser uses logs, enable for that
     tracing_log::LogTracer::init()?;
 
     let subscriber = tracing_subscriber::fmt()
-        .compact()
+        .with_max_level(config.log_level)
         .with_file(true)
         .with_line_number(true)
         .with_thread_ids(true)
-        .with_target(false)
-        .finish();
+        .with_target(false);
 
     // use that subscriber to process traces emitted after this point
-    tracing::subscriber::set_global_default(subscriber)?;
+    match config.log_format {
+        LogFormat::Compact => {
+            tracing::subscriber::set_global_default(subscriber.compact().finish())?
+        }
+        LogFormat::Full => tracing::subscriber::set_global_default(subscriber.finish())?,
+        LogFormat::Pretty => tracing::subscriber::set_global_default(subscriber.pretty().finish())?,
+    }
 
     info!("daemon started");
 
-    let jj_svc = service::JujutsuService::new();
+    let metrics = metrics::Metrics::default();
+    if let Some(address) = config.metrics_address {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(address, metrics).await {
+                error!("Metrics server failed: {err}");
+            }
+        });
+    }
+
+    info!("Store tiers: {:?}", config.store_tiers);
+    if config.store_dir.is_some() || config.cache_bytes.is_some() {
+        warn!("The store only has a memory tier, ignoring its directory and cache size");
+    }
+    let repos = repos::Repos::load(
+        &config.state_dir.join("repos"),
+        metrics.clone(),
+        config.per_repo_stores,
+    )?;
+
+    let mounts = mounts::Mounts::load(&config.state_dir.join("mounts"), config.remount)?;
+    mounts.detach_missing_trees(&repos).await?;
+    let shutdown = Arc::new(Notify::new());
 
-    let _store = store::Store::new();
+    let jj_svc = service::JujutsuService::new(repos.clone(), mounts.clone(), config.concurrency);
+    let mut health = health::Health::new(repos.clone(), mounts.clone());
+    if let Address::Tcp(_) = &config.address {
+        health = health.without_control();
+    }
+    if let Some(upstream) = &config.upstream {
+        health = health.with_upstream(upstream)?;
+    }
+    let control_svc = control::ControlService::new(
+        repos,
+        mounts.clone(),
+        health.clone(),
+        metrics.clone(),
+        shutdown.clone(),
+    );
+    let health_svc = health::HealthService::new(health.clone());
 
     let reflection_svc = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
         .build()?;
 
-    info!("Serving jj gRPC interface");
-    Server::builder()
+    let router = Server::builder()
+        .layer(metrics::RpcMetricsLayer::new(metrics))
         .add_service(reflection_svc)
         .add_service(jj_svc)
-        .serve(addr)
-        .await?;
+        .add_service(health_svc);
+    let shutdown = stop_requested(shutdown, health)?;
 
+    info!("Serving jj gRPC interface on {}", config.address);
+    match &config.address {
+        Address::Unix(path) => {
+            let listener = socket::bind(path)?;
+            let _pidfile = pidfile::PidFile::create(&proto::pidfile_path(path))?;
+            // Only the owning user can reach the socket, so it may also
+            // control the daemon
+            router
+                .add_service(control_svc)
+                .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown)
+                .await?;
+            unmount(&mounts);
+            // Nobody else will clean up after us
+            let _ = std::fs::remove_file(path);
+        }
+        Address::Tcp(address) => {
+            router
+                .serve_with_shutdown(config::tcp_listen_addr(address)?, shutdown)
+                .await?;
+            unmount(&mounts);
+        }
+    }
+
+    info!("daemon stopped");
     Ok(())
 }
+
+/// Resolves once the daemon is asked to stop, by the Shutdown RPC, SIGINT or
+/// SIGTERM. The server then stops accepting connections and waits for the
+/// requests in flight.
+fn stop_requested(
+    shutdown: Arc<Notify>,
+    health: health::Health,
+) -> std::io::Result<impl std::future::Future<Output = ()>> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = shutdown.notified() => {}
+            _ = interrupt.recv() => info!("Received SIGINT, shutting down"),
+            _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+        }
+        health.stopping();
+    })
+}
+
+/// Flushes the mount registry and unmounts every working copy, once no more
+/// requests can touch them.
+fn unmount(mounts: &mounts::Mounts) {
+    if let Err(err) = mounts.flush() {
+        error!("Failed to flush the mount registry: {err}");
+    }
+    mounts.unmount_all();
+}
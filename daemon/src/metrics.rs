@@ -0,0 +1,336 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, StatusCode,
+};
+use prost::Message;
+use tonic::codegen::{http, BoxFuture};
+use tower::{Layer, Service};
+use tracing::info;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The only storage tier so far.
+const MEMORY_TIER: &str = "memory";
+
+/// Method label of requests for paths the daemon doesn't serve, so clients
+/// can't add a series per path they make up.
+const UNKNOWN_METHOD: &str = "unknown";
+
+/// Served by `tonic_reflection`, so missing from `proto::FILE_DESCRIPTOR_SET`.
+const REFLECTION_METHOD: &str = "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo";
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations at or below each of `LATENCY_BUCKETS`.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (count, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    /// Calls by method and gRPC status code.
+    rpcs: BTreeMap<(String, String), u64>,
+    rpc_latency: BTreeMap<String, Histogram>,
+    /// Store reads by object kind, tier and whether they were found.
+    store_lookups: BTreeMap<(&'static str, &'static str, bool), u64>,
+    /// Object bytes by kind and direction, "in" for writes and "out" for reads.
+    store_bytes: BTreeMap<(&'static str, &'static str), u64>,
+}
+
+/// Counters and latency histograms, rendered in the Prometheus text format.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    pub fn rpc(&self, method: &str, code: &str, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .rpcs
+            .entry((method.to_string(), code.to_string()))
+            .or_default() += 1;
+        registry
+            .rpc_latency
+            .entry(method.to_string())
+            .or_default()
+            .observe(elapsed);
+    }
+
+    pub fn store_lookup(&self, kind: &'static str, hit: bool) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .store_lookups
+            .entry((kind, MEMORY_TIER, hit))
+            .or_default() += 1;
+    }
+
+    pub fn bytes_in(&self, kind: &'static str, bytes: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.store_bytes.entry((kind, "in")).or_default() += bytes;
+    }
+
+    pub fn bytes_out(&self, kind: &'static str, bytes: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.store_bytes.entry((kind, "out")).or_default() += bytes;
+    }
+
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP cultivate_rpc_requests_total gRPC calls handled.\n");
+        out.push_str("# TYPE cultivate_rpc_requests_total counter\n");
+        for ((method, code), count) in &registry.rpcs {
+            let _ = writeln!(
+                out,
+                "cultivate_rpc_requests_total{{method=\"{method}\",code=\"{code}\"}} {count}"
+            );
+        }
+
+        out.push_str("# HELP cultivate_rpc_duration_seconds Time to handle gRPC calls.\n");
+        out.push_str("# TYPE cultivate_rpc_duration_seconds histogram\n");
+        for (method, histogram) in &registry.rpc_latency {
+            histogram.render(
+                &mut out,
+                "cultivate_rpc_duration_seconds",
+                &format!("method=\"{method}\""),
+            );
+        }
+
+        out.push_str("# HELP cultivate_store_lookups_total Store reads by outcome.\n");
+        out.push_str("# TYPE cultivate_store_lookups_total counter\n");
+        for ((kind, tier, hit), count) in &registry.store_lookups {
+            let result = if *hit { "hit" } else { "miss" };
+            let _ = writeln!(
+                out,
+                "cultivate_store_lookups_total{{kind=\"{kind}\",tier=\"{tier}\",result=\"{result}\"}} {count}"
+            );
+        }
+
+        out.push_str(
+            "# HELP cultivate_store_bytes_total Object bytes written to and read from the store.\n",
+        );
+        out.push_str("# TYPE cultivate_store_bytes_total counter\n");
+        for ((kind, direction), bytes) in &registry.store_bytes {
+            let _ = writeln!(
+                out,
+                "cultivate_store_bytes_total{{kind=\"{kind}\",direction=\"{direction}\"}} {bytes}"
+            );
+        }
+        out
+    }
+}
+
+/// Serves `metrics` at `/metrics` on `address` until the daemon exits.
+pub async fn serve(address: SocketAddr, metrics: Metrics) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: http::Request<Body>| {
+                let response = if request.uri().path() == "/metrics" {
+                    Response::builder()
+                        .header("content-type", "text/plain; version=0.0.4")
+                        .body(Body::from(metrics.render()))
+                } else {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::empty())
+                };
+                async move { response }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&address)?.serve(make_service);
+    info!("Serving metrics on http://{}/metrics", server.local_addr());
+    server.await
+}
+
+/// The path of every gRPC method the daemon serves, `/package.Service/Method`.
+fn known_methods() -> BTreeSet<String> {
+    let descriptors = prost_types::FileDescriptorSet::decode(proto::FILE_DESCRIPTOR_SET)
+        .expect("the compiled in descriptor set is valid");
+    let mut methods = BTreeSet::from([REFLECTION_METHOD.to_string()]);
+    for file in &descriptors.file {
+        for service in &file.service {
+            for method in &service.method {
+                methods.insert(format!(
+                    "/{}.{}/{}",
+                    file.package(),
+                    service.name(),
+                    method.name()
+                ));
+            }
+        }
+    }
+    methods
+}
+
+/// Records the method, status and latency of every gRPC call.
+#[derive(Clone, Debug)]
+pub struct RpcMetricsLayer {
+    metrics: Metrics,
+    methods: Arc<BTreeSet<String>>,
+}
+
+impl RpcMetricsLayer {
+    pub fn new(metrics: Metrics) -> Self {
+        RpcMetricsLayer {
+            metrics,
+            methods: Arc::new(known_methods()),
+        }
+    }
+}
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+            methods: self.methods.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcMetrics<S> {
+    inner: S,
+    metrics: Metrics,
+    methods: Arc<BTreeSet<String>>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RpcMetrics<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let path = request.uri().path();
+        let method = if self.methods.contains(path) {
+            path.to_string()
+        } else {
+            UNKNOWN_METHOD.to_string()
+        };
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            // Failed calls carry their status in the headers, successful ones
+            // only in the trailers.
+            let code = match &response {
+                Ok(response) => response
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|code| code.to_str().ok())
+                    .unwrap_or("0"),
+                Err(_) => "transport",
+            };
+            metrics.rpc(&method, code, started.elapsed());
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.rpc("/control.Control/Metrics", "0", Duration::from_millis(3));
+        metrics.rpc("/control.Control/Metrics", "5", Duration::from_secs(20));
+        metrics.store_lookup("tree", true);
+        metrics.store_lookup("tree", false);
+        metrics.bytes_in("file", 10);
+
+        let text = metrics.render();
+        for line in [
+            "cultivate_rpc_requests_total{method=\"/control.Control/Metrics\",code=\"0\"} 1",
+            "cultivate_rpc_requests_total{method=\"/control.Control/Metrics\",code=\"5\"} 1",
+            "cultivate_rpc_duration_seconds_bucket{method=\"/control.Control/Metrics\",le=\"0.001\"} 0",
+            "cultivate_rpc_duration_seconds_bucket{method=\"/control.Control/Metrics\",le=\"0.005\"} 1",
+            "cultivate_rpc_duration_seconds_bucket{method=\"/control.Control/Metrics\",le=\"+Inf\"} 2",
+            "cultivate_rpc_duration_seconds_count{method=\"/control.Control/Metrics\"} 2",
+            "cultivate_store_lookups_total{kind=\"tree\",tier=\"memory\",result=\"hit\"} 1",
+            "cultivate_store_lookups_total{kind=\"tree\",tier=\"memory\",result=\"miss\"} 1",
+            "cultivate_store_bytes_total{kind=\"file\",direction=\"in\"} 10",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_methods() {
+        let metrics = Metrics::default();
+        let mut service = RpcMetricsLayer::new(metrics.clone()).layer(tower::service_fn(
+            |_: http::Request<()>| async { Ok::<_, Infallible>(http::Response::new(())) },
+        ));
+        for path in [
+            "/jj_interface.JujutsuInterface/ReadTree",
+            REFLECTION_METHOD,
+            "/yak/1",
+            "/yak/2",
+        ] {
+            let request = http::Request::builder().uri(path).body(()).unwrap();
+            service.call(request).await.unwrap();
+        }
+
+        let text = metrics.render();
+        for line in [
+            "cultivate_rpc_requests_total{method=\"/jj_interface.JujutsuInterface/ReadTree\",code=\"0\"} 1",
+            "cultivate_rpc_requests_total{method=\"/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo\",code=\"0\"} 1",
+            "cultivate_rpc_requests_total{method=\"unknown\",code=\"0\"} 2",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+        assert!(!text.contains("yak"));
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::{repos::Repos, store::Id};
+
+/// The operation and workspace a working copy was last checked out by.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckoutState {
+    pub op_id: Vec<u8>,
+    pub workspace_id: Vec<u8>,
+}
+
+/// A working copy the daemon serves.
+#[derive(Clone, Debug)]
+pub struct Mount {
+    /// The repository the working copy belongs to.
+    pub repo_id: String,
+    /// The tree the working copy had at its last snapshot.
+    pub tree_id: Id,
+    /// Unset until jj finishes initializing the working copy.
+    pub checkout_state: Option<CheckoutState>,
+}
+
+/// On-disk form of the registry. Only what a restarted daemon needs to
+/// remount is kept.
+#[derive(Clone, PartialEq, Message)]
+struct MountRegistry {
+    #[prost(message, repeated, tag = "1")]
+    mounts: Vec<MountRecord>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MountRecord {
+    #[prost(string, tag = "1")]
+    path: String,
+    #[prost(bytes = "vec", tag = "2")]
+    tree_id: Vec<u8>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    op_id: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    workspace_id: Option<Vec<u8>>,
+    /// Unmounted, but kept for when the path is initialized again.
+    #[prost(bool, tag = "5")]
+    detached: bool,
+    /// Empty for mounts of the default repository.
+    #[prost(string, tag = "6")]
+    repo_id: String,
+}
+
+#[derive(Debug, Default)]
+struct Registered {
+    mounts: BTreeMap<String, Mount>,
+    /// Mounts unmounted with their state kept.
+    detached: BTreeMap<String, Mount>,
+}
+
+/// Every working copy registered with the daemon, keyed by its path.
+///
+/// When backed by a file, every registration and state change is written
+/// through to it, so a restarted daemon can remount the same working copies.
+#[derive(Clone, Debug, Default)]
+pub struct Mounts {
+    registered: Arc<Mutex<Registered>>,
+    registry: Option<PathBuf>,
+}
+
+impl Mounts {
+    /// Remounts every working copy recorded in `registry`, which is created
+    /// on the first registration if it does not exist. Without `remount`,
+    /// they are kept unmounted until reattached to.
+    pub fn load(registry: &Path, remount: bool) -> io::Result<Self> {
+        let mut registered = Registered::default();
+        match fs::read(registry) {
+            Ok(bytes) => {
+                let records = MountRegistry::decode(bytes.as_slice())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                for record in records.mounts {
+                    let tree_id = record.tree_id.try_into().map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid tree id for {}", record.path),
+                        )
+                    })?;
+                    let checkout_state =
+                        record
+                            .op_id
+                            .zip(record.workspace_id)
+                            .map(|(op_id, workspace_id)| CheckoutState {
+                                op_id,
+                                workspace_id,
+                            });
+                    let mount = Mount {
+                        repo_id: record.repo_id,
+                        tree_id,
+                        checkout_state,
+                    };
+                    if record.detached || !remount {
+                        registered.detached.insert(record.path, mount);
+                    } else {
+                        info!("Remounting {}", record.path);
+                        registered.mounts.insert(record.path, mount);
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(Mounts {
+            registered: Arc::new(Mutex::new(registered)),
+            registry: Some(registry.to_path_buf()),
+        })
+    }
+
+    /// Registers a new working copy of `repo_id` at `path` checked out at
+    /// `tree_id`, discarding any state kept for it. Fails with
+    /// `AlreadyExists` if the path is mounted.
+    pub fn mount(&self, path: &str, repo_id: &str, tree_id: Id) -> io::Result<()> {
+        let mut registered = self.registered.lock().unwrap();
+        if registered.mounts.contains_key(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{path} is already mounted"),
+            ));
+        }
+        info!("Mounting {path}");
+        registered.detached.remove(path);
+        let mount = Mount {
+            repo_id: repo_id.to_string(),
+            tree_id,
+            checkout_state: None,
+        };
+        registered.mounts.insert(path.to_string(), mount);
+        self.persist(&registered)
+    }
+
+    /// Serves `path` with the state it was unmounted with. Returns whether
+    /// there is such a state, or the path is mounted already.
+    pub fn reattach(&self, path: &str) -> io::Result<bool> {
+        let mut registered = self.registered.lock().unwrap();
+        if registered.mounts.contains_key(path) {
+            return Ok(true);
+        }
+        let Some(mount) = registered.detached.remove(path) else {
+            return Ok(false);
+        };
+        info!("Reattaching {path}");
+        registered.mounts.insert(path.to_string(), mount);
+        self.persist(&registered)?;
+        Ok(true)
+    }
+
+    /// Detaches the mounts whose tree is missing from their repository's
+    /// store, keeping their state, and returns their paths. Objects are only
+    /// kept in memory, so after a restart only mounts at the empty tree can
+    /// be served again. Remounting the others at their last recorded tree
+    /// can't work until the object store is persistent.
+    pub async fn detach_missing_trees(&self, repos: &Repos) -> io::Result<Vec<String>> {
+        let mut detached = vec![];
+        for (path, mount) in self.list() {
+            if !repos.has_tree(&mount.repo_id, mount.tree_id).await {
+                warn!("Detaching {path}, its tree is missing from the store");
+                self.unmount(&path, true)?;
+                detached.push(path);
+            }
+        }
+        Ok(detached)
+    }
+
+    /// Stops serving the working copy at `path`, returning its last state.
+    /// With `keep_state`, reattaching to the path restores that state.
+    pub fn unmount(&self, path: &str, keep_state: bool) -> io::Result<Option<Mount>> {
+        let mut registered = self.registered.lock().unwrap();
+        let Some(mount) = registered.mounts.remove(path) else {
+            return Ok(None);
+        };
+        info!("Unmounting {path}");
+        if keep_state {
+            registered.detached.insert(path.to_string(), mount.clone());
+        }
+        self.persist(&registered)?;
+        Ok(Some(mount))
+    }
+
+    pub fn get(&self, path: &str) -> Option<Mount> {
+        self.registered.lock().unwrap().mounts.get(path).cloned()
+    }
+
+    /// The state kept for `path` when it was unmounted, if it is not mounted.
+    pub fn get_detached(&self, path: &str) -> Option<Mount> {
+        self.registered.lock().unwrap().detached.get(path).cloned()
+    }
+
+    /// Applies `f` to the mount at `path`, if there is one.
+    pub fn update<T>(&self, path: &str, f: impl FnOnce(&mut Mount) -> T) -> io::Result<Option<T>> {
+        let mut registered = self.registered.lock().unwrap();
+        let Some(mount) = registered.mounts.get_mut(path) else {
+            return Ok(None);
+        };
+        let result = f(mount);
+        self.persist(&registered)?;
+        Ok(Some(result))
+    }
+
+    /// Every mount with its path, in path order.
+    pub fn list(&self) -> Vec<(String, Mount)> {
+        let registered = self.registered.lock().unwrap();
+        registered
+            .mounts
+            .iter()
+            .map(|(path, mount)| (path.clone(), mount.clone()))
+            .collect()
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        let registered = self.registered.lock().unwrap();
+        registered.mounts.keys().cloned().collect()
+    }
+
+    /// Writes the registry out again, in case an earlier write failed.
+    pub fn flush(&self) -> io::Result<()> {
+        let registered = self.registered.lock().unwrap();
+        self.persist(&registered)
+    }
+
+    /// Stops serving every working copy. They stay registered, so the next
+    /// daemon remounts them.
+    pub fn unmount_all(&self) {
+        let mut registered = self.registered.lock().unwrap();
+        for path in registered.mounts.keys() {
+            info!("Unmounting {path}");
+        }
+        registered.mounts.clear();
+    }
+
+    /// Atomically replaces the registry with `registered`.
+    fn persist(&self, registered: &Registered) -> io::Result<()> {
+        let Some(registry) = &self.registry else {
+            return Ok(());
+        };
+        let mounted = registered.mounts.iter().map(|entry| (entry, false));
+        let detached = registered.detached.iter().map(|entry| (entry, true));
+        let records = MountRegistry {
+            mounts: mounted
+                .chain(detached)
+                .map(|((path, mount), detached)| MountRecord {
+                    path: path.clone(),
+                    tree_id: mount.tree_id.to_vec(),
+                    op_id: mount.checkout_state.as_ref().map(|s| s.op_id.clone()),
+                    workspace_id: mount
+                        .checkout_state
+                        .as_ref()
+                        .map(|s| s.workspace_id.clone()),
+                    detached,
+                    repo_id: mount.repo_id.clone(),
+                })
+                .collect(),
+        };
+        let dir = registry.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(&records.encode_to_vec())?;
+        file.as_file().sync_all()?;
+        file.persist(registry)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remount_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = dir.path().join("mounts");
+
+        let mounts = Mounts::load(&registry, true).unwrap();
+        mounts.mount("/tmp/yak", "1234", [1; 32]).unwrap();
+        mounts.mount("/tmp/shaved", "", [2; 32]).unwrap();
+        let checkout_state = CheckoutState {
+            op_id: vec![3; 64],
+            workspace_id: b"default".to_vec(),
+        };
+        mounts
+            .update("/tmp/yak", |mount| {
+                mount.checkout_state = Some(checkout_state.clone());
+                mount.tree_id = [4; 32];
+            })
+            .unwrap()
+            .unwrap();
+        mounts.unmount_all();
+        assert!(mounts.paths().is_empty());
+
+        let restarted = Mounts::load(&registry, true).unwrap();
+        assert_eq!(restarted.paths(), vec!["/tmp/shaved", "/tmp/yak"]);
+        let yak = restarted.get("/tmp/yak").unwrap();
+        assert_eq!(yak.repo_id, "1234");
+        assert_eq!(yak.tree_id, [4; 32]);
+        assert_eq!(yak.checkout_state, Some(checkout_state));
+        let shaved = restarted.get("/tmp/shaved").unwrap();
+        assert_eq!(shaved.tree_id, [2; 32]);
+        assert_eq!(shaved.checkout_state, None);
+
+        let detached = Mounts::load(&registry, false).unwrap();
+        assert!(detached.paths().is_empty());
+        assert!(detached.reattach("/tmp/yak").unwrap());
+        assert_eq!(detached.get("/tmp/yak").unwrap().tree_id, [4; 32]);
+    }
+
+    #[tokio::test]
+    async fn detach_missing_trees() {
+        let repos = Repos::default();
+        let empty_tree_id = repos.store("").unwrap().get_empty_tree_id();
+        let mounts = Mounts::default();
+        mounts.mount("/tmp/yak", "", [1; 32]).unwrap();
+        mounts.mount("/tmp/shaved", "", empty_tree_id).unwrap();
+        mounts.mount("/tmp/unknown", "1234", empty_tree_id).unwrap();
+
+        let detached = mounts.detach_missing_trees(&repos).await.unwrap();
+        assert_eq!(detached, vec!["/tmp/unknown", "/tmp/yak"]);
+        assert_eq!(mounts.paths(), vec!["/tmp/shaved"]);
+        assert_eq!(mounts.get_detached("/tmp/yak").unwrap().tree_id, [1; 32]);
+    }
+
+    #[test]
+    fn unmount_keeping_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = dir.path().join("mounts");
+
+        let mounts = Mounts::load(&registry, true).unwrap();
+        mounts.mount("/tmp/yak", "", [1; 32]).unwrap();
+        mounts.mount("/tmp/shaved", "", [2; 32]).unwrap();
+        mounts
+            .update("/tmp/yak", |mount| mount.tree_id = [3; 32])
+            .unwrap()
+            .unwrap();
+
+        let yak = mounts.unmount("/tmp/yak", true).unwrap().unwrap();
+        assert_eq!(yak.tree_id, [3; 32]);
+        assert!(mounts.unmount("/tmp/shaved", false).unwrap().is_some());
+        assert!(mounts.unmount("/tmp/shaved", false).unwrap().is_none());
+        assert!(mounts.paths().is_empty());
+
+        // Neither is remounted, but the kept state survives a restart
+        let restarted = Mounts::load(&registry, true).unwrap();
+        assert!(restarted.paths().is_empty());
+        assert!(restarted.reattach("/tmp/yak").unwrap());
+        assert!(restarted.reattach("/tmp/yak").unwrap());
+        assert!(!restarted.reattach("/tmp/shaved").unwrap());
+        assert_eq!(restarted.get("/tmp/yak").unwrap().tree_id, [3; 32]);
+        let err = restarted.mount("/tmp/yak", "", [0; 32]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+}
@@ -0,0 +1,26 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Records the daemon's pid for as long as it is alive, so tooling can tell a
+/// running daemon from one that went away without cleaning up.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use prost::Message;
+use tracing::info;
+
+use crate::{
+    metrics::Metrics,
+    store::{Id, Store},
+};
+
+/// The repository of calls that name none, which predates repository ids.
+pub const DEFAULT_REPO: &str = "";
+
+/// On-disk form of the registry. Objects are only kept in memory, so only
+/// the ids survive a restart.
+#[derive(Clone, PartialEq, Message)]
+struct RepoRegistry {
+    #[prost(message, repeated, tag = "1")]
+    repos: Vec<RepoRecord>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RepoRecord {
+    #[prost(string, tag = "1")]
+    id: String,
+}
+
+/// Every repository the daemon knows, keyed by the id `Initialize` gave it.
+///
+/// Repositories share one store unless created with per-repo stores, in
+/// which case objects written for one are invisible to the others. The
+/// default repository always uses the shared store.
+#[derive(Clone, Debug)]
+pub struct Repos {
+    shared: Store,
+    stores: Arc<Mutex<BTreeMap<String, Store>>>,
+    per_repo_stores: bool,
+    metrics: Metrics,
+    registry: Option<PathBuf>,
+}
+
+impl Repos {
+    /// Loads the repositories recorded in `registry`, which is created on
+    /// the first new repository if it does not exist.
+    pub fn load(registry: &Path, metrics: Metrics, per_repo_stores: bool) -> io::Result<Self> {
+        let mut repos = Repos {
+            shared: Store::new().with_metrics(metrics.clone()),
+            stores: Default::default(),
+            per_repo_stores,
+            metrics,
+            registry: Some(registry.to_path_buf()),
+        };
+        match fs::read(registry) {
+            Ok(bytes) => {
+                let records = RepoRegistry::decode(bytes.as_slice())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                let stores = records
+                    .repos
+                    .into_iter()
+                    .map(|record| (record.id, repos.new_store()))
+                    .collect();
+                repos.stores = Arc::new(Mutex::new(stores));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(repos)
+    }
+
+    /// Records a new repository, returning its id.
+    pub fn create(&self) -> io::Result<String> {
+        let mut stores = self.stores.lock().unwrap();
+        let id = new_repo_id(stores.len());
+        info!("Creating repo {id}");
+        stores.insert(id.clone(), self.new_store());
+        self.persist(&stores)?;
+        Ok(id)
+    }
+
+    /// The store of repository `id`, or `None` if there is no such repository.
+    pub fn store(&self, id: &str) -> Option<Store> {
+        if id == DEFAULT_REPO {
+            return Some(self.shared.clone());
+        }
+        self.stores.lock().unwrap().get(id).cloned()
+    }
+
+    /// Whether the store of repository `id` holds the tree `tree_id`.
+    pub async fn has_tree(&self, id: &str, tree_id: Id) -> bool {
+        match self.store(id) {
+            Some(store) => store.has_tree(tree_id).await,
+            None => false,
+        }
+    }
+
+    fn new_store(&self) -> Store {
+        if self.per_repo_stores {
+            Store::new().with_metrics(self.metrics.clone())
+        } else {
+            self.shared.clone()
+        }
+    }
+
+    /// Atomically replaces the registry with `stores`.
+    fn persist(&self, stores: &BTreeMap<String, Store>) -> io::Result<()> {
+        let Some(registry) = &self.registry else {
+            return Ok(());
+        };
+        let records = RepoRegistry {
+            repos: stores
+                .keys()
+                .map(|id| RepoRecord { id: id.clone() })
+                .collect(),
+        };
+        let dir = registry.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(&records.encode_to_vec())?;
+        file.as_file().sync_all()?;
+        file.persist(registry)?;
+        Ok(())
+    }
+}
+
+impl Default for Repos {
+    /// Only the default repository, with nothing written to disk.
+    fn default() -> Self {
+        let metrics = Metrics::default();
+        Repos {
+            shared: Store::new().with_metrics(metrics.clone()),
+            stores: Default::default(),
+            per_repo_stores: false,
+            metrics,
+            registry: None,
+        }
+    }
+}
+
+/// A hex id unlikely to be handed out by this or any other daemon.
+fn new_repo_id(salt: usize) -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&now.as_nanos().to_le_bytes());
+    hasher.update(&std::process::id().to_le_bytes());
+    hasher.update(&salt.to_le_bytes());
+    hex::encode(&hasher.finalize().as_bytes()[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::File;
+
+    #[tokio::test]
+    async fn per_repo_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = dir.path().join("repos");
+        let file = File {
+            content: b"yak".to_vec(),
+        };
+
+        let shared = Repos::load(&registry, Metrics::default(), false).unwrap();
+        let yak = shared.create().unwrap();
+        let shaved = shared.create().unwrap();
+        assert_ne!(yak, shaved);
+        let id = shared.store(&yak).unwrap().write_file(file.clone()).await;
+        assert!(shared.store(&shaved).unwrap().has_file(id).await);
+        assert!(shared.store(DEFAULT_REPO).unwrap().has_file(id).await);
+        assert!(shared.store("unknown").is_none());
+
+        // The ids survive a restart, the objects do not
+        let separate = Repos::load(&registry, Metrics::default(), true).unwrap();
+        let yak_store = separate.store(&yak).unwrap();
+        assert!(!yak_store.has_file(id).await);
+        yak_store.write_file(file).await;
+        assert!(!separate.store(&shaved).unwrap().has_file(id).await);
+        assert!(!separate.store(DEFAULT_REPO).unwrap().has_file(id).await);
+    }
+}
@@ -1,17 +1,204 @@
-use prost::Message;
 use proto::jj_interface::*;
 use tonic::{Request, Response, Status};
 use tracing::info;
 
+use crate::{
+    mounts::{self, Mount, Mounts},
+    repos::{Repos, DEFAULT_REPO},
+    store::{Id, Store, TreeEntry},
+};
+
 #[derive(Debug)]
-pub struct JujutsuService {}
+pub struct JujutsuService {
+    repos: Repos,
+    mounts: Mounts,
+    /// Overrides the concurrency the store would report.
+    concurrency: Option<usize>,
+}
 
 impl JujutsuService {
-    pub fn new() -> jujutsu_interface_server::JujutsuInterfaceServer<Self> {
-        jujutsu_interface_server::JujutsuInterfaceServer::new(JujutsuService {})
+    pub fn new(
+        repos: Repos,
+        mounts: Mounts,
+        concurrency: Option<usize>,
+    ) -> jujutsu_interface_server::JujutsuInterfaceServer<Self> {
+        jujutsu_interface_server::JujutsuInterfaceServer::new(JujutsuService {
+            repos,
+            mounts,
+            concurrency,
+        })
+    }
+
+    /// The store of the repository `request` is scoped to.
+    #[allow(clippy::result_large_err)]
+    fn store<T>(&self, request: &Request<T>) -> Result<Store, Status> {
+        let repo_id = repo_id(request)?;
+        self.repos
+            .store(&repo_id)
+            .ok_or_else(|| Status::not_found(format!("Unknown repo {repo_id:?}")))
+    }
+
+    /// The mount at `path`, which must belong to the repository `request` is
+    /// scoped to.
+    #[allow(clippy::result_large_err)]
+    fn mount<T>(&self, request: &Request<T>, path: &str) -> Result<Mount, Status> {
+        let mount = self.mounts.get(path).ok_or_else(|| not_mounted(path))?;
+        if mount.repo_id != repo_id(request)? {
+            return Err(Status::permission_denied(format!(
+                "{path} belongs to another repo"
+            )));
+        }
+        Ok(mount)
+    }
+}
+
+/// The repository named by the call's metadata, the default one if unnamed.
+#[allow(clippy::result_large_err)]
+fn repo_id<T>(request: &Request<T>) -> Result<String, Status> {
+    match request.metadata().get(proto::REPO_ID_METADATA) {
+        Some(repo_id) => repo_id
+            .to_str()
+            .map(str::to_string)
+            .map_err(|_| Status::invalid_argument("Repo id is not ASCII")),
+        None => Ok(DEFAULT_REPO.to_string()),
     }
 }
 
+async fn read_tree_by_id(store: &Store, tree_id: TreeId) -> Result<Tree, Status> {
+    let tree = store
+        .get_tree(to_id(tree_id.tree_id)?)
+        .await
+        .ok_or_else(|| Status::not_found("Store does not contain tree"))?;
+    Ok(tree.as_proto())
+}
+
+async fn write_tree_proto(
+    store: &Store,
+    tree: Tree,
+    expected_id: Option<TreeId>,
+) -> Result<TreeId, Status> {
+    let tree: crate::store::Tree = tree.try_into().map_err(invalid_object)?;
+    if let Some(expected_id) = expected_id {
+        verify_id("tree", expected_id.tree_id, tree.get_hash())?;
+    }
+    check_references(store, &tree).await?;
+    let tree_id = store.write_tree(tree).await;
+    Ok(TreeId {
+        tree_id: tree_id.to_vec(),
+    })
+}
+
+async fn read_file_by_id(store: &Store, file_id: FileId) -> Result<File, Status> {
+    let file = store
+        .get_file(to_id(file_id.file_id)?)
+        .await
+        .ok_or_else(|| Status::not_found("Store does not contain file"))?;
+    file.as_proto()
+        .map_err(|err| Status::internal(format!("Failed to compress file: {err}")))
+}
+
+async fn contains(store: &Store, typed_id: &TypedId) -> Result<bool, Status> {
+    let id = to_id(typed_id.id.clone())?;
+    Ok(match typed_id.kind() {
+        ObjectKind::File => store.has_file(id).await,
+        ObjectKind::Symlink => store.has_symlink(id).await,
+        ObjectKind::Tree => store.has_tree(id).await,
+        ObjectKind::Commit => store.has_commit(id).await,
+        // Conflicts are not stored yet
+        ObjectKind::Conflict => false,
+        ObjectKind::Unspecified => {
+            return Err(Status::invalid_argument("Object kind must be specified"))
+        }
+    })
+}
+
+/// Trees may only point at objects the store already has, so a reader
+/// never finds a dangling entry.
+async fn check_references(store: &Store, tree: &crate::store::Tree) -> Result<(), Status> {
+    for (name, entry) in &tree.entries {
+        let (kind, present) = match entry {
+            TreeEntry::File { id, .. } => ("file", store.has_file(*id).await),
+            TreeEntry::TreeId(id) => ("tree", store.has_tree(*id).await),
+            TreeEntry::SymlinkId(id) => ("symlink", store.has_symlink(*id).await),
+            // Conflicts are not stored yet
+            TreeEntry::ConflictId(_) => ("conflict", false),
+        };
+        if !present {
+            return Err(Status::invalid_argument(format!(
+                "Tree entry `{name}` references a {kind} missing from the store"
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn write_file_proto(
+    store: &Store,
+    file: File,
+    expected_id: Option<FileId>,
+) -> Result<FileId, Status> {
+    let file: crate::store::File = file.try_into().map_err(invalid_object)?;
+    if let Some(expected_id) = expected_id {
+        verify_id("file", expected_id.file_id, file.get_hash())?;
+    }
+    let file_id = store.write_file(file).await;
+    Ok(FileId {
+        file_id: file_id.to_vec(),
+    })
+}
+
+fn missing(kind: &str) -> Status {
+    Status::invalid_argument(format!("Request is missing the {kind} to write"))
+}
+
+fn invalid_object(err: model::ProtoError) -> Status {
+    Status::invalid_argument(err.to_string())
+}
+
+/// Checks a client supplied id against the hash the daemon computed itself.
+#[allow(clippy::result_large_err)]
+fn verify_id(kind: &str, expected: Vec<u8>, actual: Id) -> Result<(), Status> {
+    if expected != actual {
+        return Err(Status::invalid_argument(format!(
+            "Id mismatch for {kind}: client sent {}, content hashes to {}",
+            hex::encode(expected),
+            hex::encode(actual)
+        )));
+    }
+    Ok(())
+}
+
+/// Batched writes either claim an id for every object or for none of them.
+#[allow(clippy::result_large_err)]
+fn expected_ids<T>(objects: usize, ids: Vec<T>) -> Result<Vec<Option<T>>, Status> {
+    if ids.is_empty() {
+        return Ok(std::iter::repeat_with(|| None).take(objects).collect());
+    }
+    if ids.len() != objects {
+        return Err(Status::invalid_argument(format!(
+            "Expected {objects} ids, got {}",
+            ids.len()
+        )));
+    }
+    Ok(ids.into_iter().map(Some).collect())
+}
+
+fn registry_error(err: std::io::Error) -> Status {
+    Status::internal(format!("Failed to record mount state: {err}"))
+}
+
+fn not_mounted(path: &str) -> Status {
+    Status::not_found(format!("No working copy is mounted at {path}"))
+}
+
+/// Object ids on the wire are raw bytes, the store only deals in fixed size hashes.
+#[allow(clippy::result_large_err)]
+fn to_id(bytes: Vec<u8>) -> Result<Id, Status> {
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        Status::invalid_argument(format!("Invalid id length: {}", bytes.len()))
+    })
+}
+
 #[tonic::async_trait]
 impl jujutsu_interface_server::JujutsuInterface for JujutsuService {
     #[tracing::instrument(skip(self))]
@@ -20,8 +207,67 @@ impl jujutsu_interface_server::JujutsuInterface for JujutsuService {
         request: Request<InitializeReq>,
     ) -> Result<Response<InitializeReply>, Status> {
         let req = request.into_inner();
-        info!("Initializing a new repo at {}", req.path);
-        todo!();
+        let path = std::path::Path::new(&req.path);
+        if !path.is_absolute() {
+            return Err(Status::invalid_argument(format!(
+                "{} is not an absolute path",
+                req.path
+            )));
+        }
+        if req.reattach {
+            info!("Reattaching to {}", req.path);
+            let nothing_to_reattach = || {
+                Status::failed_precondition(format!(
+                    "There is no mount of {} to reattach to",
+                    req.path
+                ))
+            };
+            let mount = self
+                .mounts
+                .get(&req.path)
+                .or_else(|| self.mounts.get_detached(&req.path))
+                .ok_or_else(nothing_to_reattach)?;
+            // Objects don't survive a restart, so neither does any tree but
+            // the empty one
+            if !self.repos.has_tree(&mount.repo_id, mount.tree_id).await {
+                return Err(Status::failed_precondition(format!(
+                    "The tree {} was last mounted with is missing from the store",
+                    req.path
+                )));
+            }
+            if !self.mounts.reattach(&req.path).map_err(registry_error)? {
+                return Err(nothing_to_reattach());
+            }
+            return Ok(Response::new(InitializeReply {
+                repo_id: mount.repo_id,
+            }));
+        }
+        if !path.is_dir() {
+            return Err(Status::failed_precondition(format!(
+                "{} is not a directory",
+                req.path
+            )));
+        }
+        // Checked up front so a failed call leaves no repo behind
+        if self.mounts.get(&req.path).is_some() {
+            return Err(Status::already_exists(format!(
+                "{} is already mounted",
+                req.path
+            )));
+        }
+        let repo_id = self
+            .repos
+            .create()
+            .map_err(|err| Status::internal(format!("Failed to record the new repo: {err}")))?;
+        info!("Initializing repo {repo_id} at {}", req.path);
+        let empty_tree_id = self.repos.store(&repo_id).unwrap().get_empty_tree_id();
+        self.mounts
+            .mount(&req.path, &repo_id, empty_tree_id)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::AlreadyExists => Status::already_exists(err.to_string()),
+                _ => registry_error(err),
+            })?;
+        Ok(Response::new(InitializeReply { repo_id }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -30,8 +276,10 @@ impl jujutsu_interface_server::JujutsuInterface for JujutsuService {
         request: Request<GetTreeStateReq>,
     ) -> Result<Response<GetTreeStateReply>, Status> {
         info!("Getting tree state");
-        let _req = request.into_inner();
-        todo!()
+        let mount = self.mount(&request, &request.get_ref().working_copy_path)?;
+        Ok(Response::new(GetTreeStateReply {
+            tree_id: mount.tree_id.to_vec(),
+        }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -40,8 +288,18 @@ impl jujutsu_interface_server::JujutsuInterface for JujutsuService {
         request: Request<GetCheckoutStateReq>,
     ) -> Result<Response<CheckoutState>, Status> {
         info!("Getting checkout state");
-        let _req = request.into_inner();
-        todo!()
+        let mount = self.mount(&request, &request.get_ref().working_copy_path)?;
+        let req = request.into_inner();
+        let checkout_state = mount.checkout_state.ok_or_else(|| {
+            Status::failed_precondition(format!(
+                "{} has not been checked out yet",
+                req.working_copy_path
+            ))
+        })?;
+        Ok(Response::new(CheckoutState {
+            op_id: checkout_state.op_id,
+            workspace_id: checkout_state.workspace_id,
+        }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -49,8 +307,24 @@ impl jujutsu_interface_server::JujutsuInterface for JujutsuService {
         &self,
         request: Request<SetCheckoutStateReq>,
     ) -> Result<Response<SetCheckoutStateReply>, Status> {
-        let _req = request.into_inner();
-        todo!()
+        self.mount(&request, &request.get_ref().working_copy_path)?;
+        let req = request.into_inner();
+        let CheckoutState {
+            op_id,
+            workspace_id,
+        } = req
+            .checkout_state
+            .ok_or_else(|| Status::invalid_argument("Missing checkout state"))?;
+        self.mounts
+            .update(&req.working_copy_path, |mount| {
+                mount.checkout_state = Some(mounts::CheckoutState {
+                    op_id,
+                    workspace_id,
+                });
+            })
+            .map_err(registry_error)?
+            .ok_or_else(|| not_mounted(&req.working_copy_path))?;
+        Ok(Response::new(SetCheckoutStateReply {}))
     }
 
     #[tracing::instrument(skip(self))]
@@ -58,70 +332,869 @@ impl jujutsu_interface_server::JujutsuInterface for JujutsuService {
         &self,
         request: Request<SnapshotReq>,
     ) -> Result<Response<SnapshotReply>, Status> {
-        let _req = request.into_inner();
-        todo!()
+        // Nothing is written through the daemon yet, so the last snapshot is
+        // still current.
+        let mount = self.mount(&request, &request.get_ref().working_copy_path)?;
+        Ok(Response::new(SnapshotReply {
+            tree_id: mount.tree_id.to_vec(),
+        }))
     }
 
     #[tracing::instrument(skip(self))]
     async fn get_empty_tree_id(
         &self,
-        _request: Request<GetEmptyTreeIdReq>,
+        request: Request<GetEmptyTreeIdReq>,
     ) -> Result<Response<TreeId>, Status> {
-        todo!()
+        let tree_id = self.store(&request)?.get_empty_tree_id().to_vec();
+        Ok(Response::new(TreeId { tree_id }))
     }
 
     #[tracing::instrument(skip(self))]
     async fn concurrency(
         &self,
-        _request: Request<ConcurrencyRequest>,
+        request: Request<ConcurrencyRequest>,
     ) -> Result<Response<ConcurrencyReply>, Status> {
-        todo!()
+        let store = self.store(&request)?;
+        let concurrency = self.concurrency.unwrap_or_else(|| store.concurrency());
+        Ok(Response::new(ConcurrencyReply {
+            estimated_concurrency: concurrency.try_into().unwrap_or(i32::MAX),
+        }))
     }
 
     #[tracing::instrument(skip(self))]
-    async fn write_file(&self, _request: Request<File>) -> Result<Response<FileId>, Status> {
-        todo!()
+    async fn write_file(&self, request: Request<WriteFileReq>) -> Result<Response<FileId>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let file = req.file.ok_or_else(|| missing("file"))?;
+        let file_id = write_file_proto(&store, file, req.expected_id).await?;
+        Ok(Response::new(file_id))
     }
 
     #[tracing::instrument(skip(self))]
-    async fn read_file(&self, _request: Request<FileId>) -> Result<Response<File>, Status> {
-        todo!()
+    async fn read_file(&self, request: Request<FileId>) -> Result<Response<File>, Status> {
+        let store = self.store(&request)?;
+        let file = read_file_by_id(&store, request.into_inner()).await?;
+        Ok(Response::new(file))
     }
 
     #[tracing::instrument(skip(self))]
     async fn write_symlink(
         &self,
-        request: Request<Symlink>,
+        request: Request<WriteSymlinkReq>,
     ) -> Result<Response<SymlinkId>, Status> {
-        let symlink = request.into_inner();
-        let _symlink_id = *blake3::hash(&symlink.encode_to_vec()).as_bytes();
-        todo!()
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let symlink: crate::store::Symlink = req.symlink.ok_or_else(|| missing("symlink"))?.into();
+        if let Some(expected_id) = req.expected_id {
+            verify_id("symlink", expected_id.symlink_id, symlink.get_hash())?;
+        }
+        let symlink_id = store.write_symlink(symlink).await;
+        Ok(Response::new(SymlinkId {
+            symlink_id: symlink_id.to_vec(),
+        }))
     }
 
     #[tracing::instrument(skip(self))]
     async fn read_symlink(&self, request: Request<SymlinkId>) -> Result<Response<Symlink>, Status> {
-        let _symlink_id = request.into_inner();
-        todo!()
+        let store = self.store(&request)?;
+        let symlink_id = request.into_inner();
+        let symlink = store
+            .get_symlink(to_id(symlink_id.symlink_id)?)
+            .await
+            .ok_or_else(|| Status::not_found("Store does not contain symlink"))?;
+        Ok(Response::new(symlink.as_proto()))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn write_tree(&self, request: Request<WriteTreeReq>) -> Result<Response<TreeId>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let tree = req.tree.ok_or_else(|| missing("tree"))?;
+        let tree_id = write_tree_proto(&store, tree, req.expected_id).await?;
+        Ok(Response::new(tree_id))
     }
 
     #[tracing::instrument(skip(self))]
-    async fn write_tree(&self, _request: Request<Tree>) -> Result<Response<TreeId>, Status> {
-        todo!()
+    async fn read_tree(&self, request: Request<TreeId>) -> Result<Response<Tree>, Status> {
+        let store = self.store(&request)?;
+        let tree = read_tree_by_id(&store, request.into_inner()).await?;
+        Ok(Response::new(tree))
     }
 
     #[tracing::instrument(skip(self))]
-    async fn read_tree(&self, _request: Request<TreeId>) -> Result<Response<Tree>, Status> {
-        todo!()
+    async fn write_commit(
+        &self,
+        request: Request<WriteCommitReq>,
+    ) -> Result<Response<CommitId>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let commit: crate::store::Commit = req
+            .commit
+            .ok_or_else(|| missing("commit"))?
+            .try_into()
+            .map_err(invalid_object)?;
+        if commit.parents.is_empty() {
+            return Err(Status::invalid_argument(
+                "Cannot write a commit with no parents",
+            ));
+        }
+        if let Some(expected_id) = req.expected_id {
+            verify_id("commit", expected_id.commit_id, commit.get_hash())?;
+        }
+        let commit_id = store.write_commit(commit).await;
+        Ok(Response::new(CommitId {
+            commit_id: commit_id.to_vec(),
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_commit(&self, request: Request<CommitId>) -> Result<Response<Commit>, Status> {
+        let store = self.store(&request)?;
+        let commit_id = request.into_inner();
+        let commit = store
+            .get_commit(to_id(commit_id.commit_id)?)
+            .await
+            .ok_or_else(|| Status::not_found("Store does not contain commit"))?;
+        Ok(Response::new(commit.as_proto()))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_trees(
+        &self,
+        request: Request<ReadTreesReq>,
+    ) -> Result<Response<ReadTreesReply>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let mut trees = Vec::with_capacity(req.tree_ids.len());
+        for tree_id in req.tree_ids {
+            trees.push(read_tree_by_id(&store, tree_id).await?);
+        }
+        Ok(Response::new(ReadTreesReply { trees }))
     }
 
     #[tracing::instrument(skip(self))]
-    async fn write_commit(&self, request: Request<Commit>) -> Result<Response<CommitId>, Status> {
-        let _commit = request.into_inner();
-        todo!()
+    async fn write_trees(
+        &self,
+        request: Request<WriteTreesReq>,
+    ) -> Result<Response<WriteTreesReply>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let expected_ids = expected_ids(req.trees.len(), req.expected_ids)?;
+        let mut tree_ids = Vec::with_capacity(req.trees.len());
+        // In order, so trees can reference those earlier in the same batch
+        for (tree, expected_id) in req.trees.into_iter().zip(expected_ids) {
+            tree_ids.push(write_tree_proto(&store, tree, expected_id).await?);
+        }
+        Ok(Response::new(WriteTreesReply { tree_ids }))
     }
 
     #[tracing::instrument(skip(self))]
-    async fn read_commit(&self, _request: Request<CommitId>) -> Result<Response<Commit>, Status> {
-        todo!()
+    async fn read_files(
+        &self,
+        request: Request<ReadFilesReq>,
+    ) -> Result<Response<ReadFilesReply>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let mut files = Vec::with_capacity(req.file_ids.len());
+        for file_id in req.file_ids {
+            files.push(read_file_by_id(&store, file_id).await?);
+        }
+        Ok(Response::new(ReadFilesReply { files }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn write_files(
+        &self,
+        request: Request<WriteFilesReq>,
+    ) -> Result<Response<WriteFilesReply>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let expected_ids = expected_ids(req.files.len(), req.expected_ids)?;
+        let mut file_ids = Vec::with_capacity(req.files.len());
+        for (file, expected_id) in req.files.into_iter().zip(expected_ids) {
+            file_ids.push(write_file_proto(&store, file, expected_id).await?);
+        }
+        Ok(Response::new(WriteFilesReply { file_ids }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn find_missing(
+        &self,
+        request: Request<FindMissingReq>,
+    ) -> Result<Response<FindMissingReply>, Status> {
+        let store = self.store(&request)?;
+        let req = request.into_inner();
+        let mut missing = vec![];
+        for typed_id in req.ids {
+            if !contains(&store, &typed_id).await? {
+                missing.push(typed_id);
+            }
+        }
+        Ok(Response::new(FindMissingReply { missing }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use jujutsu_interface_server::JujutsuInterface;
+    use model::content_hash;
+
+    use super::*;
+
+    fn service() -> JujutsuService {
+        JujutsuService {
+            repos: Repos::default(),
+            mounts: Mounts::default(),
+            concurrency: None,
+        }
+    }
+
+    /// `message` as sent by a client of `repo_id`.
+    fn scoped<T>(repo_id: &str, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert(proto::REPO_ID_METADATA, repo_id.parse().unwrap());
+        request
+    }
+
+    fn write_commit(commit: &Commit) -> Request<WriteCommitReq> {
+        Request::new(WriteCommitReq {
+            commit: Some(commit.clone()),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn concurrency() {
+        let estimated_concurrency = |service: JujutsuService| async move {
+            service
+                .concurrency(Request::new(ConcurrencyRequest {}))
+                .await
+                .unwrap()
+                .into_inner()
+                .estimated_concurrency
+        };
+        assert!(estimated_concurrency(service()).await >= 1);
+
+        let configured = JujutsuService {
+            concurrency: Some(3),
+            ..service()
+        };
+        assert_eq!(estimated_concurrency(configured).await, 3);
+    }
+
+    #[tokio::test]
+    async fn write_commit_parents() {
+        let service = service();
+        let mut commit = model::Commit::default().as_proto();
+
+        // No parents
+        assert_matches!(
+            service.write_commit(write_commit(&commit)).await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+                && status.message().contains("no parents")
+        );
+
+        // Only root commit as parent
+        commit.parents = vec![vec![0; 32]];
+        let first_id = service
+            .write_commit(write_commit(&commit))
+            .await
+            .unwrap()
+            .into_inner();
+        let first_commit = service
+            .read_commit(Request::new(first_id.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(first_commit, commit);
+
+        // Merge commit
+        commit.parents = vec![first_id.commit_id, vec![0; 32]];
+        let merge_id = service
+            .write_commit(write_commit(&commit))
+            .await
+            .unwrap()
+            .into_inner();
+        let merge_commit = service
+            .read_commit(Request::new(merge_id))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(merge_commit, commit);
+    }
+
+    #[tokio::test]
+    async fn batch_files_round_trip() {
+        let service = service();
+        let content = b"the last yak".to_vec();
+        let files = vec![
+            File {
+                data: content.clone(),
+                ..Default::default()
+            },
+            File::default(),
+            // Compression must not change the id
+            File::compress(&content).unwrap(),
+        ];
+        let file_ids = service
+            .write_files(Request::new(WriteFilesReq {
+                files,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .file_ids;
+        assert_eq!(file_ids.len(), 3);
+        assert_eq!(file_ids[0], file_ids[2]);
+        assert_ne!(file_ids[0], file_ids[1]);
+        assert_eq!(file_ids[0].file_id, content_hash::file_id(&content));
+
+        let read: Vec<_> = service
+            .read_files(Request::new(ReadFilesReq { file_ids }))
+            .await
+            .unwrap()
+            .into_inner()
+            .files
+            .into_iter()
+            .map(|file| file.decompress().unwrap())
+            .collect();
+        assert_eq!(read, vec![content.clone(), vec![], content]);
+    }
+
+    #[tokio::test]
+    async fn batch_trees_round_trip() {
+        let service = service();
+        let empty_tree_id = service
+            .get_empty_tree_id(Request::new(GetEmptyTreeIdReq {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let tree = Tree {
+            entries: vec![tree::Entry {
+                name: "dir".to_string(),
+                value: Some(TreeValue {
+                    value: Some(tree_value::Value::TreeId(empty_tree_id.tree_id.clone())),
+                }),
+            }],
+        };
+        let tree_ids = service
+            .write_trees(Request::new(WriteTreesReq {
+                trees: vec![tree.clone()],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .tree_ids;
+
+        let trees = service
+            .read_trees(Request::new(ReadTreesReq {
+                tree_ids: vec![tree_ids[0].clone(), empty_tree_id],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .trees;
+        assert_eq!(trees, vec![tree, Tree::default()]);
+    }
+
+    #[tokio::test]
+    async fn find_missing_objects() {
+        let service = service();
+        let file_id = service
+            .write_file(Request::new(WriteFileReq {
+                file: Some(File {
+                    data: b"the last yak".to_vec(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .file_id;
+        let empty_tree_id = service
+            .get_empty_tree_id(Request::new(GetEmptyTreeIdReq {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .tree_id;
+
+        let typed_id = |kind: ObjectKind, id: Vec<u8>| TypedId {
+            kind: kind.into(),
+            id,
+        };
+        let ids = vec![
+            typed_id(ObjectKind::File, file_id.clone()),
+            typed_id(ObjectKind::File, vec![1; 32]),
+            typed_id(ObjectKind::Tree, empty_tree_id),
+            // Present, but not as a tree
+            typed_id(ObjectKind::Tree, file_id),
+            typed_id(ObjectKind::Commit, vec![2; 32]),
+        ];
+        let missing = service
+            .find_missing(Request::new(FindMissingReq { ids: ids.clone() }))
+            .await
+            .unwrap()
+            .into_inner()
+            .missing;
+        assert_eq!(
+            missing,
+            vec![ids[1].clone(), ids[3].clone(), ids[4].clone()]
+        );
+
+        assert_matches!(
+            service
+                .find_missing(Request::new(FindMissingReq {
+                    ids: vec![typed_id(ObjectKind::Unspecified, vec![0; 32])],
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn read_missing_objects() {
+        let service = service();
+        fn code<T>(result: Result<T, Status>) -> tonic::Code {
+            result.map(|_| ()).unwrap_err().code()
+        }
+        let read_file = service.read_file(Request::new(FileId {
+            file_id: vec![1; 32],
+        }));
+        assert_eq!(code(read_file.await), tonic::Code::NotFound);
+        let read_symlink = service.read_symlink(Request::new(SymlinkId {
+            symlink_id: vec![1; 32],
+        }));
+        assert_eq!(code(read_symlink.await), tonic::Code::NotFound);
+        let read_tree = service.read_tree(Request::new(TreeId {
+            tree_id: vec![1; 32],
+        }));
+        assert_eq!(code(read_tree.await), tonic::Code::NotFound);
+        let read_commit = service.read_commit(Request::new(CommitId {
+            commit_id: vec![1; 32],
+        }));
+        assert_eq!(code(read_commit.await), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn batch_read_missing_tree() {
+        let service = service();
+        assert_matches!(
+            service
+                .read_trees(Request::new(ReadTreesReq {
+                    tree_ids: vec![TreeId {
+                        tree_id: vec![1; 32]
+                    }],
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn write_rejects_id_mismatch() {
+        let service = service();
+        let file = File {
+            data: b"the last yak".to_vec(),
+            ..Default::default()
+        };
+        assert_matches!(
+            service
+                .write_files(Request::new(WriteFilesReq {
+                    files: vec![file.clone()],
+                    expected_ids: vec![FileId {
+                        file_id: vec![1; 32]
+                    }],
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+        let store = service.repos.store(DEFAULT_REPO).unwrap();
+        let hash = content_hash::file_id(b"the last yak");
+        assert!(!store.has_file(hash).await);
+
+        // Single writes are verified the same way
+        assert_matches!(
+            service
+                .write_file(Request::new(WriteFileReq {
+                    file: Some(file.clone()),
+                    expected_id: Some(FileId {
+                        file_id: vec![1; 32]
+                    }),
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+        assert!(!store.has_file(hash).await);
+        let symlink = Symlink {
+            target: "yak".to_string(),
+        };
+        assert_matches!(
+            service
+                .write_symlink(Request::new(WriteSymlinkReq {
+                    symlink: Some(symlink.clone()),
+                    expected_id: Some(SymlinkId {
+                        symlink_id: vec![1; 32]
+                    }),
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+        assert!(
+            !store
+                .has_symlink(model::Symlink::from(symlink).get_hash())
+                .await
+        );
+        let tree = Tree::default();
+        assert_matches!(
+            service
+                .write_tree(Request::new(WriteTreeReq {
+                    tree: Some(tree),
+                    expected_id: Some(TreeId {
+                        tree_id: vec![1; 32]
+                    }),
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+        let mut commit = model::Commit::default().as_proto();
+        commit.parents = vec![vec![0; 32]];
+        assert_matches!(
+            service
+                .write_commit(Request::new(WriteCommitReq {
+                    commit: Some(commit.clone()),
+                    expected_id: Some(CommitId {
+                        commit_id: vec![1; 32]
+                    }),
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+        let commit_hash = model::Commit::try_from(commit.clone()).unwrap().get_hash();
+        assert!(!store.has_commit(commit_hash).await);
+        let commit_id = service
+            .write_commit(Request::new(WriteCommitReq {
+                commit: Some(commit),
+                expected_id: Some(CommitId {
+                    commit_id: commit_hash.to_vec(),
+                }),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(commit_id.commit_id, commit_hash);
+
+        let file_id = FileId {
+            file_id: hash.to_vec(),
+        };
+        let file_ids = service
+            .write_files(Request::new(WriteFilesReq {
+                files: vec![file.clone()],
+                expected_ids: vec![file_id.clone()],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .file_ids;
+        assert_eq!(file_ids, vec![file_id]);
+
+        // Every file needs an id once any is given
+        assert_matches!(
+            service
+                .write_files(Request::new(WriteFilesReq {
+                    files: vec![file.clone(), file],
+                    expected_ids: file_ids,
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn write_tree_referential_integrity() {
+        let service = service();
+        let entry = |name: &str, value| tree::Entry {
+            name: name.to_string(),
+            value: Some(TreeValue { value: Some(value) }),
+        };
+        let dangling = Tree {
+            entries: vec![entry(
+                "file",
+                tree_value::Value::File(tree_value::File {
+                    id: vec![1; 32],
+                    executable: false,
+                }),
+            )],
+        };
+        assert_matches!(
+            service
+                .write_tree(Request::new(WriteTreeReq {
+                    tree: Some(dangling),
+                    ..Default::default()
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+
+        // A batch may reference trees written earlier in the same batch
+        let child = Tree {
+            entries: vec![entry(
+                "link",
+                tree_value::Value::SymlinkId(
+                    service
+                        .write_symlink(Request::new(WriteSymlinkReq {
+                            symlink: Some(Symlink {
+                                target: "yak".to_string(),
+                            }),
+                            ..Default::default()
+                        }))
+                        .await
+                        .unwrap()
+                        .into_inner()
+                        .symlink_id,
+                ),
+            )],
+        };
+        let child_id = model::Tree::try_from(child.clone()).unwrap().get_hash();
+        let parent = Tree {
+            entries: vec![entry("dir", tree_value::Value::TreeId(child_id.to_vec()))],
+        };
+        let tree_ids = service
+            .write_trees(Request::new(WriteTreesReq {
+                trees: vec![child.clone(), parent.clone()],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .tree_ids;
+        assert_eq!(tree_ids[0].tree_id, child_id);
+
+        let service = self::service();
+        assert_matches!(
+            service
+                .write_trees(Request::new(WriteTreesReq {
+                    trees: vec![parent, child],
+                    ..Default::default()
+                }))
+                .await,
+            Err(status) if status.code() == tonic::Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn working_copy_state() {
+        let service = service();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        let code = |status: Status| status.code();
+
+        let not_mounted = service
+            .get_tree_state(Request::new(GetTreeStateReq {
+                working_copy_path: path.clone(),
+            }))
+            .await
+            .map_err(code);
+        assert_matches!(not_mounted, Err(tonic::Code::NotFound));
+
+        let repo_id = service
+            .initialize(Request::new(InitializeReq {
+                path: path.clone(),
+                reattach: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .repo_id;
+        let tree_state = service
+            .get_tree_state(scoped(
+                &repo_id,
+                GetTreeStateReq {
+                    working_copy_path: path.clone(),
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(tree_state.tree_id, Store::new().get_empty_tree_id());
+
+        let unset = service
+            .get_checkout_state(scoped(
+                &repo_id,
+                GetCheckoutStateReq {
+                    working_copy_path: path.clone(),
+                },
+            ))
+            .await
+            .map_err(code);
+        assert_matches!(unset, Err(tonic::Code::FailedPrecondition));
+
+        let checkout_state = CheckoutState {
+            op_id: vec![1; 64],
+            workspace_id: b"default".to_vec(),
+        };
+        service
+            .set_checkout_state(scoped(
+                &repo_id,
+                SetCheckoutStateReq {
+                    working_copy_path: path.clone(),
+                    checkout_state: Some(checkout_state.clone()),
+                },
+            ))
+            .await
+            .unwrap();
+        let stored = service
+            .get_checkout_state(scoped(
+                &repo_id,
+                GetCheckoutStateReq {
+                    working_copy_path: path.clone(),
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stored, checkout_state);
+
+        let snapshot = service
+            .snapshot(scoped(
+                &repo_id,
+                SnapshotReq {
+                    working_copy_path: path,
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(snapshot.tree_id, tree_state.tree_id);
+    }
+
+    #[tokio::test]
+    async fn initialize_statuses() {
+        let service = service();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        let initialize = |path: &str, reattach| {
+            let request = Request::new(InitializeReq {
+                path: path.to_string(),
+                reattach,
+            });
+            let service = &service;
+            async move {
+                service
+                    .initialize(request)
+                    .await
+                    .map(|_| ())
+                    .map_err(|status| status.code())
+            }
+        };
+
+        assert_eq!(
+            initialize("yak", false).await,
+            Err(tonic::Code::InvalidArgument)
+        );
+        let missing = dir.path().join("missing");
+        assert_eq!(
+            initialize(missing.to_str().unwrap(), false).await,
+            Err(tonic::Code::FailedPrecondition)
+        );
+        assert_eq!(
+            initialize(&path, true).await,
+            Err(tonic::Code::FailedPrecondition)
+        );
+
+        assert_eq!(initialize(&path, false).await, Ok(()));
+        assert_eq!(
+            initialize(&path, false).await,
+            Err(tonic::Code::AlreadyExists)
+        );
+        assert_eq!(initialize(&path, true).await, Ok(()));
+        assert_eq!(service.mounts.paths(), vec![path.clone()]);
+
+        // As after a restart, which loses every tree but the empty one
+        service
+            .mounts
+            .update(&path, |mount| mount.tree_id = [1; 32])
+            .unwrap();
+        service.mounts.unmount(&path, true).unwrap();
+        assert_eq!(
+            initialize(&path, true).await,
+            Err(tonic::Code::FailedPrecondition)
+        );
+        assert!(service.mounts.paths().is_empty());
+    }
+
+    #[tokio::test]
+    async fn repos_are_isolated() {
+        let state = tempfile::tempdir().unwrap();
+        let service = JujutsuService {
+            repos: Repos::load(&state.path().join("repos"), Default::default(), true).unwrap(),
+            ..service()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        let repo_id = service
+            .initialize(Request::new(InitializeReq {
+                path: path.clone(),
+                reattach: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .repo_id;
+        assert_ne!(repo_id, DEFAULT_REPO);
+
+        let file = File {
+            data: b"the last yak".to_vec(),
+            ..Default::default()
+        };
+        let file_id = service
+            .write_file(scoped(
+                &repo_id,
+                WriteFileReq {
+                    file: Some(file),
+                    ..Default::default()
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        service
+            .read_file(scoped(&repo_id, file_id.clone()))
+            .await
+            .unwrap();
+        assert_matches!(
+            service.read_file(Request::new(file_id.clone())).await,
+            Err(status) if status.code() == tonic::Code::NotFound
+        );
+        assert_matches!(
+            service.read_file(scoped("unknown", file_id)).await,
+            Err(status) if status.code() == tonic::Code::NotFound
+        );
+
+        let tree_state = GetTreeStateReq {
+            working_copy_path: path.clone(),
+        };
+        service
+            .get_tree_state(scoped(&repo_id, tree_state.clone()))
+            .await
+            .unwrap();
+        assert_matches!(
+            service.get_tree_state(Request::new(tree_state)).await,
+            Err(status) if status.code() == tonic::Code::PermissionDenied
+        );
+        let reattached = service
+            .initialize(Request::new(InitializeReq {
+                path,
+                reattach: true,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reattached.repo_id, repo_id);
     }
 }
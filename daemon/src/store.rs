@@ -1,64 +1,266 @@
-pub type Id = [u8; 32];
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
-#[derive(Clone, Debug)]
-pub enum TreeEntry {
-    _File { id: Id, executable: bool },
-    _TreeId(Id),
-    _SymlinkId(Id),
-    _ConflictId(Id),
+pub use model::{Commit, File, Id, Symlink, Tree, TreeEntry};
+use prost::Message;
+
+use crate::metrics::Metrics;
+
+/// How much of one kind of object the store holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ObjectStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StoreStats {
+    pub commits: ObjectStats,
+    pub trees: ObjectStats,
+    pub files: ObjectStats,
+    pub symlinks: ObjectStats,
+    /// Bytes of every write, including ones the store already had.
+    pub written_bytes: u64,
+}
+
+impl StoreStats {
+    pub fn stored_bytes(&self) -> u64 {
+        self.commits.bytes + self.trees.bytes + self.files.bytes + self.symlinks.bytes
+    }
+
+    /// Bytes written per byte stored. Above 1 when writes were deduplicated.
+    pub fn dedup_ratio(&self) -> f64 {
+        match self.stored_bytes() {
+            0 => 1.0,
+            stored => self.written_bytes as f64 / stored as f64,
+        }
+    }
+}
+
+/// File contents are counted as is, other objects by their encoded size.
+trait StoredSize {
+    /// Names the kind of object in metrics.
+    const KIND: &'static str;
+
+    fn stored_size(&self) -> u64;
+}
+
+impl StoredSize for Commit {
+    const KIND: &'static str = "commit";
+
+    fn stored_size(&self) -> u64 {
+        self.as_proto().encoded_len() as u64
+    }
+}
+
+impl StoredSize for Tree {
+    const KIND: &'static str = "tree";
+
+    fn stored_size(&self) -> u64 {
+        self.as_proto().encoded_len() as u64
+    }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct Tree {
-    pub _entries: Vec<(String, TreeEntry)>
+impl StoredSize for File {
+    const KIND: &'static str = "file";
+
+    fn stored_size(&self) -> u64 {
+        self.content.len() as u64
+    }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct Symlink {
-    // TODO maybe represent as PathBuf
-    pub _target: String,
+impl StoredSize for Symlink {
+    const KIND: &'static str = "symlink";
+
+    fn stored_size(&self) -> u64 {
+        self.as_proto().encoded_len() as u64
+    }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct File {
-    pub _content: Vec<u8>,
+fn object_stats<T: StoredSize>(objects: &Mutex<HashMap<Id, T>>) -> ObjectStats {
+    let objects = objects.lock().unwrap();
+    ObjectStats {
+        count: objects.len() as u64,
+        bytes: objects.values().map(StoredSize::stored_size).sum(),
+    }
 }
 
 /// Stores mount-agnostic information like Trees or Commits. Unaware of filesystem information.
 #[derive(Clone, Debug)]
 pub struct Store {
+    /// Empty sha identity
+    pub empty_tree_id: Id,
+
+    commits: Arc<Mutex<HashMap<Id, Commit>>>,
+    trees: Arc<Mutex<HashMap<Id, Tree>>>,
+    files: Arc<Mutex<HashMap<Id, File>>>,
+    symlinks: Arc<Mutex<HashMap<Id, Symlink>>>,
+    written_bytes: Arc<AtomicU64>,
+    metrics: Metrics,
 }
 
 impl Store {
     pub fn new() -> Self {
+        let tree = Tree::default();
+        let empty_tree_id = tree.get_hash();
+        let trees = HashMap::from([(empty_tree_id, tree)]);
+
         Store {
+            empty_tree_id,
+            commits: Default::default(),
+            trees: Arc::new(Mutex::new(trees)),
+            files: Default::default(),
+            symlinks: Default::default(),
+            written_bytes: Default::default(),
+            metrics: Default::default(),
         }
     }
 
-    pub async fn get_tree(&self, _id: Id) -> Option<Tree> {
-        todo!()
+    /// Records lookups and object bytes in `metrics`.
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        Store { metrics, ..self }
     }
 
-    #[tracing::instrument]
-    pub async fn write_tree(&self, _tree: Tree) -> Id {
-        todo!()
+    pub fn stats(&self) -> StoreStats {
+        StoreStats {
+            commits: object_stats(&self.commits),
+            trees: object_stats(&self.trees),
+            files: object_stats(&self.files),
+            symlinks: object_stats(&self.symlinks),
+            written_bytes: self.written_bytes.load(Ordering::Relaxed),
+        }
     }
 
-    pub async fn get_file(&self, _id: Id) -> Option<File> {
-        todo!()
+    fn count_write<T: StoredSize>(&self, object: &T) {
+        let size = object.stored_size();
+        self.written_bytes.fetch_add(size, Ordering::Relaxed);
+        self.metrics.bytes_in(T::KIND, size);
     }
 
-    #[tracing::instrument]
-    pub async fn write_file(&self, _file: File) -> Id {
-        todo!()
+    fn count_read<T: StoredSize>(&self, object: Option<&T>) {
+        self.metrics.store_lookup(T::KIND, object.is_some());
+        if let Some(object) = object {
+            self.metrics.bytes_out(T::KIND, object.stored_size());
+        }
     }
 
-    pub async fn get_symlink(&self, _id: Id) -> Option<Symlink> {
-        todo!()
+    pub fn get_empty_tree_id(&self) -> Id {
+        self.empty_tree_id
     }
 
-    #[tracing::instrument]
-    pub async fn write_symlink(&self, _symlink: Symlink) -> Id {
-        todo!()
+    /// How many reads the store can serve in parallel. Everything is held in
+    /// memory, so this is bounded by the threads available to serve them.
+    pub fn concurrency(&self) -> usize {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    }
+
+    pub async fn has_tree(&self, id: Id) -> bool {
+        self.trees.lock().unwrap().contains_key(&id)
+    }
+
+    pub async fn get_tree(&self, id: Id) -> Option<Tree> {
+        let tree = self.trees.lock().unwrap().get(&id).cloned();
+        self.count_read(tree.as_ref());
+        tree
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn write_tree(&self, tree: Tree) -> Id {
+        let hash = tree.get_hash();
+        self.count_write(&tree);
+        let mut tree_store = self.trees.lock().unwrap();
+        tree_store.insert(hash, tree);
+        hash
+    }
+
+    pub async fn has_file(&self, id: Id) -> bool {
+        self.files.lock().unwrap().contains_key(&id)
+    }
+
+    pub async fn get_file(&self, id: Id) -> Option<File> {
+        let file = self.files.lock().unwrap().get(&id).cloned();
+        self.count_read(file.as_ref());
+        file
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn write_file(&self, file: File) -> Id {
+        let hash = file.get_hash();
+        self.count_write(&file);
+        let mut file_store = self.files.lock().unwrap();
+        file_store.insert(hash, file);
+        hash
+    }
+
+    pub async fn has_symlink(&self, id: Id) -> bool {
+        self.symlinks.lock().unwrap().contains_key(&id)
+    }
+
+    pub async fn get_symlink(&self, id: Id) -> Option<Symlink> {
+        let symlink = self.symlinks.lock().unwrap().get(&id).cloned();
+        self.count_read(symlink.as_ref());
+        symlink
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn write_symlink(&self, symlink: Symlink) -> Id {
+        let hash = symlink.get_hash();
+        self.count_write(&symlink);
+        let mut symlink_store = self.symlinks.lock().unwrap();
+        symlink_store.insert(hash, symlink);
+        hash
+    }
+
+    pub async fn has_commit(&self, id: Id) -> bool {
+        self.commits.lock().unwrap().contains_key(&id)
+    }
+
+    pub async fn get_commit(&self, id: Id) -> Option<Commit> {
+        let commit = self.commits.lock().unwrap().get(&id).cloned();
+        self.count_read(commit.as_ref());
+        commit
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn write_commit(&self, commit: Commit) -> Id {
+        let hash = commit.get_hash();
+        self.count_write(&commit);
+        let mut commit_store = self.commits.lock().unwrap();
+        commit_store.insert(hash, commit);
+        hash
+    }
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stats() {
+        let store = Store::new();
+        let empty = store.stats();
+        assert_eq!(empty.trees.count, 1);
+        assert_eq!(empty.written_bytes, 0);
+
+        let file = File {
+            content: b"yak".to_vec(),
+        };
+        store.write_file(file.clone()).await;
+        store.write_file(file).await;
+        let stats = store.stats();
+        assert_eq!(stats.files, ObjectStats { count: 1, bytes: 3 });
+        assert_eq!(stats.written_bytes, 6);
+        assert!(stats.dedup_ratio() > 1.0);
     }
 }
@@ -0,0 +1,301 @@
+//! Portable, stable hashing suitable for identifying values
+//!
+//! Adapted from the Jujutsu source code for blake3. This is the single
+//! definition of object ids shared by the CLI and the daemon. Ids are always
+//! computed over uncompressed content, compression is purely a wire and
+//! storage concern.
+
+use std::collections::BTreeMap;
+
+use proto::jj_interface::ObjectKind;
+
+use crate::{Commit, Signature, Symlink, Timestamp, Tree, TreeEntry};
+
+pub type Id = [u8; 32];
+
+/// Portable, stable hashing suitable for identifying values
+///
+/// Variable-length sequences should hash a 64-bit little-endian representation
+/// of their length, then their elements in order. Unordered containers should
+/// order their elements according to their `Ord` implementation. Enums should
+/// hash a 32-bit little-endian encoding of the ordinal number of the enum
+/// variant, then the variant's fields in lexical order.
+pub trait ContentHash {
+    /// Update the hasher state with this object's content
+    fn update(&self, state: &mut blake3::Hasher);
+}
+
+/// The 256-bit BLAKE3 content hash
+pub fn blake3(x: &(impl ContentHash + ?Sized)) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    x.update(&mut hasher);
+    hasher.finalize()
+}
+
+/// Hashes the object kind first so objects of different kinds with the
+/// same encoding never share an id.
+fn object_id(kind: ObjectKind, x: &(impl ContentHash + ?Sized)) -> Id {
+    let mut hasher = blake3::Hasher::new();
+    (kind as i32).update(&mut hasher);
+    x.update(&mut hasher);
+    *hasher.finalize().as_bytes()
+}
+
+/// Id of a file with the given uncompressed contents.
+pub fn file_id(content: &[u8]) -> Id {
+    object_id(ObjectKind::File, content)
+}
+
+pub fn symlink_id(symlink: &Symlink) -> Id {
+    object_id(ObjectKind::Symlink, symlink)
+}
+
+pub fn tree_id(tree: &Tree) -> Id {
+    object_id(ObjectKind::Tree, tree)
+}
+
+pub fn commit_id(commit: &Commit) -> Id {
+    object_id(ObjectKind::Commit, commit)
+}
+
+impl ContentHash for () {
+    fn update(&self, _: &mut blake3::Hasher) {}
+}
+
+impl ContentHash for bool {
+    fn update(&self, state: &mut blake3::Hasher) {
+        u8::from(*self).update(state);
+    }
+}
+
+impl ContentHash for u8 {
+    fn update(&self, state: &mut blake3::Hasher) {
+        state.update(&[*self]);
+    }
+}
+
+impl ContentHash for i32 {
+    fn update(&self, state: &mut blake3::Hasher) {
+        state.update(&self.to_le_bytes());
+    }
+}
+
+impl ContentHash for i64 {
+    fn update(&self, state: &mut blake3::Hasher) {
+        state.update(&self.to_le_bytes());
+    }
+}
+
+// Hashing bytes one at a time yields the same digest, this just avoids the
+// per-byte overhead for file contents.
+impl ContentHash for [u8] {
+    fn update(&self, state: &mut blake3::Hasher) {
+        state.update(&(self.len() as u64).to_le_bytes());
+        state.update(self);
+    }
+}
+
+impl<T: ContentHash> ContentHash for Vec<T> {
+    fn update(&self, state: &mut blake3::Hasher) {
+        state.update(&(self.len() as u64).to_le_bytes());
+        for x in self {
+            x.update(state);
+        }
+    }
+}
+
+impl ContentHash for str {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.as_bytes().update(state);
+    }
+}
+
+impl ContentHash for String {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.as_str().update(state);
+    }
+}
+
+impl<T: ContentHash> ContentHash for Option<T> {
+    fn update(&self, state: &mut blake3::Hasher) {
+        match self {
+            None => {
+                state.update(&[0]);
+            }
+            Some(x) => {
+                state.update(&[1]);
+                x.update(state);
+            }
+        }
+    }
+}
+
+impl<K: ContentHash, V: ContentHash> ContentHash for BTreeMap<K, V> {
+    fn update(&self, state: &mut blake3::Hasher) {
+        state.update(&(self.len() as u64).to_le_bytes());
+        for (k, v) in self.iter() {
+            k.update(state);
+            v.update(state);
+        }
+    }
+}
+
+impl ContentHash for Id {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.as_slice().update(state);
+    }
+}
+
+impl ContentHash for Symlink {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.target.update(state);
+    }
+}
+
+impl ContentHash for TreeEntry {
+    fn update(&self, state: &mut blake3::Hasher) {
+        match self {
+            TreeEntry::File { id, executable } => {
+                0i32.update(state);
+                id.update(state);
+                executable.update(state);
+            }
+            TreeEntry::SymlinkId(id) => {
+                1i32.update(state);
+                id.update(state);
+            }
+            TreeEntry::TreeId(id) => {
+                2i32.update(state);
+                id.update(state);
+            }
+            TreeEntry::ConflictId(id) => {
+                3i32.update(state);
+                id.update(state);
+            }
+        }
+    }
+}
+
+impl ContentHash for Tree {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.entries.update(state);
+    }
+}
+
+impl ContentHash for Timestamp {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.millis_since_epoch.update(state);
+        self.tz_offset.update(state);
+    }
+}
+
+impl ContentHash for Signature {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.name.update(state);
+        self.email.update(state);
+        self.timestamp.update(state);
+    }
+}
+
+impl ContentHash for Commit {
+    fn update(&self, state: &mut blake3::Hasher) {
+        self.parents.update(state);
+        self.predecessors.update(state);
+        self.root_tree.update(state);
+        self.uses_tree_conflict_format.update(state);
+        self.change_id.update(state);
+        self.description.update(state);
+        self.author.update(state);
+        self.committer.update(state);
+        self.secure_sig.update(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(entries: &[(&str, TreeEntry)]) -> Tree {
+        Tree {
+            entries: entries
+                .iter()
+                .map(|(name, entry)| (name.to_string(), entry.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_string_sanity() {
+        let a = "a".to_string();
+        let b = "b".to_string();
+        assert_eq!(hash(&a), hash(&a.clone()));
+        assert_ne!(hash(&a), hash(&b));
+        assert_ne!(hash(&"a".to_string()), hash(&"a\0".to_string()));
+    }
+
+    #[test]
+    fn test_option_sanity() {
+        assert_ne!(hash(&Some(42)), hash(&42));
+        assert_ne!(hash(&None::<i32>), hash(&42i32));
+    }
+
+    #[test]
+    fn test_bytes_match_generic_sequence() {
+        let bytes = b"the last yak".to_vec();
+        assert_eq!(hash(bytes.as_slice()), hash(&bytes));
+    }
+
+    #[test]
+    fn test_kinds_do_not_collide() {
+        assert_ne!(file_id(b""), tree_id(&Tree::default()));
+        assert_ne!(
+            file_id(b"target"),
+            symlink_id(&Symlink {
+                target: "target".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_tree_entry_sanity() {
+        let file = TreeEntry::File {
+            id: [1; 32],
+            executable: false,
+        };
+        let executable = TreeEntry::File {
+            id: [1; 32],
+            executable: true,
+        };
+        let subtree = TreeEntry::TreeId([1; 32]);
+        let a = tree(&[("a", file.clone())]);
+        assert_eq!(tree_id(&a), tree_id(&a.clone()));
+        assert_ne!(tree_id(&a), tree_id(&tree(&[("b", file.clone())])));
+        assert_ne!(tree_id(&a), tree_id(&tree(&[("a", executable)])));
+        assert_ne!(tree_id(&a), tree_id(&tree(&[("a", subtree)])));
+        assert_ne!(
+            tree_id(&tree(&[("ab", file.clone())])),
+            tree_id(&tree(&[("a", file)]))
+        );
+    }
+
+    #[test]
+    fn test_consistent_hashing() {
+        // Changing these changes every object id, existing stores included.
+        assert_eq!(
+            hex(&file_id(b"the last yak")),
+            "6d489dc3c185466925129e8fded09348a3a5f5b5c2736a456555224c091d6911"
+        );
+        assert_eq!(
+            hex(&tree_id(&Tree::default())),
+            "c902b6ec01a5f65b7bbe796f7e01ef301e96a4b273edb9714290f47d65aaa0f2"
+        );
+    }
+
+    fn hex(id: &Id) -> String {
+        id.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn hash(x: &(impl ContentHash + ?Sized)) -> Vec<u8> {
+        blake3(x).as_bytes().to_vec()
+    }
+}
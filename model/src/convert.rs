@@ -0,0 +1,254 @@
+//! Conversions between the object model and its wire representation.
+
+use crate::{Commit, File, Id, Signature, Symlink, Timestamp, Tree, TreeEntry};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtoError {
+    #[error("Invalid id length: expected 32 bytes, got {0}")]
+    InvalidIdLength(usize),
+    #[error("Missing field `{0}`")]
+    MissingField(&'static str),
+    #[error("Invalid file contents")]
+    File(#[from] std::io::Error),
+}
+
+fn to_id(bytes: Vec<u8>) -> Result<Id, ProtoError> {
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| ProtoError::InvalidIdLength(bytes.len()))
+}
+
+fn to_ids(ids: Vec<Vec<u8>>) -> Result<Vec<Id>, ProtoError> {
+    ids.into_iter().map(to_id).collect()
+}
+
+impl TreeEntry {
+    pub fn as_proto(&self) -> proto::jj_interface::TreeValue {
+        use proto::jj_interface::tree_value::Value;
+        let value = match self {
+            TreeEntry::File { id, executable } => {
+                Value::File(proto::jj_interface::tree_value::File {
+                    id: id.to_vec(),
+                    executable: *executable,
+                })
+            }
+            TreeEntry::TreeId(id) => Value::TreeId(id.to_vec()),
+            TreeEntry::SymlinkId(id) => Value::SymlinkId(id.to_vec()),
+            TreeEntry::ConflictId(id) => Value::ConflictId(id.to_vec()),
+        };
+        proto::jj_interface::TreeValue { value: Some(value) }
+    }
+}
+
+impl TryFrom<proto::jj_interface::TreeValue> for TreeEntry {
+    type Error = ProtoError;
+
+    fn try_from(proto: proto::jj_interface::TreeValue) -> Result<Self, Self::Error> {
+        use proto::jj_interface::tree_value::Value::*;
+        Ok(
+            match proto.value.ok_or(ProtoError::MissingField("value"))? {
+                TreeId(id) => TreeEntry::TreeId(to_id(id)?),
+                SymlinkId(id) => TreeEntry::SymlinkId(to_id(id)?),
+                ConflictId(id) => TreeEntry::ConflictId(to_id(id)?),
+                File(file) => TreeEntry::File {
+                    id: to_id(file.id)?,
+                    executable: file.executable,
+                },
+            },
+        )
+    }
+}
+
+impl Tree {
+    pub fn as_proto(&self) -> proto::jj_interface::Tree {
+        let entries = self
+            .entries
+            .iter()
+            .map(|(name, entry)| proto::jj_interface::tree::Entry {
+                name: name.clone(),
+                value: Some(entry.as_proto()),
+            })
+            .collect();
+        proto::jj_interface::Tree { entries }
+    }
+}
+
+impl TryFrom<proto::jj_interface::Tree> for Tree {
+    type Error = ProtoError;
+
+    fn try_from(proto: proto::jj_interface::Tree) -> Result<Self, Self::Error> {
+        let entries = proto
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let value = entry.value.ok_or(ProtoError::MissingField("value"))?;
+                Ok((entry.name, value.try_into()?))
+            })
+            .collect::<Result<_, ProtoError>>()?;
+        Ok(Tree { entries })
+    }
+}
+
+impl File {
+    /// Files are compressed for the wire, which never changes their id.
+    pub fn as_proto(&self) -> std::io::Result<proto::jj_interface::File> {
+        proto::jj_interface::File::compress(&self.content)
+    }
+}
+
+impl TryFrom<proto::jj_interface::File> for File {
+    type Error = ProtoError;
+
+    fn try_from(proto: proto::jj_interface::File) -> Result<Self, Self::Error> {
+        Ok(File {
+            content: proto.decompress()?,
+        })
+    }
+}
+
+impl Symlink {
+    pub fn as_proto(&self) -> proto::jj_interface::Symlink {
+        proto::jj_interface::Symlink {
+            target: self.target.clone(),
+        }
+    }
+}
+
+impl From<proto::jj_interface::Symlink> for Symlink {
+    fn from(proto: proto::jj_interface::Symlink) -> Self {
+        Symlink {
+            target: proto.target,
+        }
+    }
+}
+
+impl Signature {
+    pub fn as_proto(&self) -> proto::jj_interface::commit::Signature {
+        proto::jj_interface::commit::Signature {
+            name: self.name.clone(),
+            email: self.email.clone(),
+            timestamp: Some(proto::jj_interface::commit::Timestamp {
+                millis_since_epoch: self.timestamp.millis_since_epoch,
+                tz_offset: self.timestamp.tz_offset,
+            }),
+        }
+    }
+}
+
+impl From<proto::jj_interface::commit::Signature> for Signature {
+    fn from(proto: proto::jj_interface::commit::Signature) -> Self {
+        let timestamp = proto.timestamp.unwrap_or_default();
+        Signature {
+            name: proto.name,
+            email: proto.email,
+            timestamp: Timestamp {
+                millis_since_epoch: timestamp.millis_since_epoch,
+                tz_offset: timestamp.tz_offset,
+            },
+        }
+    }
+}
+
+impl Commit {
+    pub fn as_proto(&self) -> proto::jj_interface::Commit {
+        proto::jj_interface::Commit {
+            parents: self.parents.iter().map(|id| id.to_vec()).collect(),
+            predecessors: self.predecessors.iter().map(|id| id.to_vec()).collect(),
+            root_tree: self.root_tree.iter().map(|id| id.to_vec()).collect(),
+            uses_tree_conflict_format: self.uses_tree_conflict_format,
+            change_id: self.change_id.clone(),
+            description: self.description.clone(),
+            author: Some(self.author.as_proto()),
+            committer: Some(self.committer.as_proto()),
+            secure_sig: self.secure_sig.clone(),
+        }
+    }
+}
+
+impl TryFrom<proto::jj_interface::Commit> for Commit {
+    type Error = ProtoError;
+
+    fn try_from(proto: proto::jj_interface::Commit) -> Result<Self, Self::Error> {
+        Ok(Commit {
+            parents: to_ids(proto.parents)?,
+            predecessors: to_ids(proto.predecessors)?,
+            root_tree: to_ids(proto.root_tree)?,
+            uses_tree_conflict_format: proto.uses_tree_conflict_format,
+            change_id: proto.change_id,
+            description: proto.description,
+            author: proto.author.unwrap_or_default().into(),
+            committer: proto.committer.unwrap_or_default().into(),
+            secure_sig: proto.secure_sig,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn tree_round_trip() {
+        let tree = Tree {
+            entries: [
+                (
+                    "file".to_string(),
+                    TreeEntry::File {
+                        id: [1; 32],
+                        executable: true,
+                    },
+                ),
+                ("dir".to_string(), TreeEntry::TreeId([2; 32])),
+                ("link".to_string(), TreeEntry::SymlinkId([3; 32])),
+                ("conflict".to_string(), TreeEntry::ConflictId([4; 32])),
+            ]
+            .into(),
+        };
+        let round_trip = Tree::try_from(tree.as_proto()).unwrap();
+        assert_eq!(round_trip, tree);
+        assert_eq!(round_trip.get_hash(), tree.get_hash());
+    }
+
+    #[test]
+    fn commit_round_trip() {
+        let commit = Commit {
+            parents: vec![[0; 32]],
+            root_tree: vec![[1; 32]],
+            uses_tree_conflict_format: true,
+            change_id: vec![2; 16],
+            description: "shave the yak".to_string(),
+            ..Default::default()
+        };
+        let round_trip = Commit::try_from(commit.as_proto()).unwrap();
+        assert_eq!(round_trip, commit);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let file = File {
+            content: b"the last yak".to_vec(),
+        };
+        let round_trip = File::try_from(file.as_proto().unwrap()).unwrap();
+        assert_eq!(round_trip, file);
+    }
+
+    #[test]
+    fn invalid_ids() {
+        let mut proto = Commit::default().as_proto();
+        proto.parents = vec![vec![0; 20]];
+        assert_matches!(
+            Commit::try_from(proto),
+            Err(ProtoError::InvalidIdLength(20))
+        );
+
+        let tree = proto::jj_interface::Tree {
+            entries: vec![proto::jj_interface::tree::Entry {
+                name: "missing".to_string(),
+                value: None,
+            }],
+        };
+        assert_matches!(Tree::try_from(tree), Err(ProtoError::MissingField(_)));
+    }
+}
@@ -0,0 +1,88 @@
+//! The object model shared by the CLI and the daemon.
+//!
+//! Every object is identified by the hash of its typed representation here,
+//! so both sides agree on ids no matter how objects travel over the wire.
+
+use std::collections::BTreeMap;
+
+pub mod content_hash;
+mod convert;
+
+pub use content_hash::Id;
+pub use convert::ProtoError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TreeEntry {
+    File { id: Id, executable: bool },
+    TreeId(Id),
+    SymlinkId(Id),
+    ConflictId(Id),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tree {
+    pub entries: BTreeMap<String, TreeEntry>,
+}
+
+impl Tree {
+    pub fn get_hash(&self) -> Id {
+        content_hash::tree_id(self)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct File {
+    pub content: Vec<u8>,
+}
+
+impl File {
+    pub fn get_hash(&self) -> Id {
+        content_hash::file_id(&self.content)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symlink {
+    // TODO maybe represent as PathBuf
+    pub target: String,
+}
+
+impl Symlink {
+    pub fn get_hash(&self) -> Id {
+        content_hash::symlink_id(self)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Timestamp {
+    pub millis_since_epoch: i64,
+    pub tz_offset: i32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Commit {
+    pub parents: Vec<Id>,
+    pub predecessors: Vec<Id>,
+    /// Alternating positive and negative terms
+    pub root_tree: Vec<Id>,
+    // TODO(#1624): delete when all code paths can handle this format
+    pub uses_tree_conflict_format: bool,
+    pub change_id: Vec<u8>,
+    pub description: String,
+    pub author: Signature,
+    pub committer: Signature,
+    pub secure_sig: Option<Vec<u8>>,
+}
+
+impl Commit {
+    pub fn get_hash(&self) -> Id {
+        content_hash::commit_id(self)
+    }
+}
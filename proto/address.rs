@@ -0,0 +1,86 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Where the daemon serves `JujutsuInterface`. Local daemons listen on a Unix
+/// socket so only the owning user can reach them, TCP is for remote servers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// `unix:/path/to/socket` or `unix:///path/to/socket`
+    Unix(PathBuf),
+    /// Anything else, e.g. `http://[::1]:10000`. A bare `[::1]:10000` is
+    /// taken as `http://`, so the daemon and its clients read it alike.
+    Tcp(String),
+}
+
+impl From<&str> for Address {
+    fn from(address: &str) -> Self {
+        match address.strip_prefix("unix:") {
+            Some(path) => {
+                // `unix:///a` is the URI form of `unix:/a`
+                let path = path.strip_prefix("//").unwrap_or(path);
+                Address::Unix(PathBuf::from(path))
+            }
+            None if address.contains("://") => Address::Tcp(address.to_string()),
+            None => Address::Tcp(format!("http://{address}")),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Tcp(uri) => f.write_str(uri),
+        }
+    }
+}
+
+/// Where the daemon listens, and where clients look for it, unless configured
+/// otherwise: a socket in the user's runtime directory, or `~/.cultivate`
+/// without one.
+pub fn default_address() -> Address {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|runtime_dir| PathBuf::from(runtime_dir).join("cultivate"))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cultivate")))
+        .unwrap_or_else(|| std::env::temp_dir().join("cultivate"));
+    Address::Unix(dir.join("daemon.sock"))
+}
+
+/// A local daemon records its pid next to its socket.
+pub fn pidfile_path(socket: &Path) -> PathBuf {
+    socket.with_file_name("daemon.pid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_address() {
+        assert_eq!(
+            Address::from("unix:/run/user/1000/cultivate/daemon.sock"),
+            Address::Unix("/run/user/1000/cultivate/daemon.sock".into())
+        );
+        assert_eq!(
+            Address::from("unix:///tmp/daemon.sock"),
+            Address::Unix("/tmp/daemon.sock".into())
+        );
+        assert_eq!(
+            Address::from("http://[::1]:10000"),
+            Address::Tcp("http://[::1]:10000".to_string())
+        );
+        assert_eq!(
+            Address::from("unix:///tmp/daemon.sock").to_string(),
+            "unix:/tmp/daemon.sock"
+        );
+    }
+
+    #[test]
+    fn bare_host_and_port() {
+        let address = Address::from("127.0.0.1:10000");
+        assert_eq!(address, Address::Tcp("http://127.0.0.1:10000".to_string()));
+        assert_eq!(Address::from(address.to_string().as_str()), address);
+    }
+}
@@ -0,0 +1,78 @@
+//! File contents may be compressed on the wire, which never changes their id.
+
+use std::io::{self, Read};
+
+use crate::jj_interface::{Compression, File};
+
+/// Largest file content accepted once decompressed, so a small payload can't
+/// expand without bound.
+pub const MAX_FILE_SIZE: u64 = 1 << 30;
+
+impl File {
+    /// Compress `content` with zstd for sending over the wire.
+    pub fn compress(content: &[u8]) -> io::Result<Self> {
+        Ok(File {
+            data: zstd::stream::encode_all(content, 0)?,
+            compression: Compression::Zstd.into(),
+        })
+    }
+
+    /// The decoded file contents, regardless of wire compression.
+    pub fn decompress(self) -> io::Result<Vec<u8>> {
+        self.decompress_limited(MAX_FILE_SIZE)
+    }
+
+    /// Like `decompress`, but fails with `InvalidData` if the contents are
+    /// larger than `limit` bytes.
+    pub fn decompress_limited(self, limit: u64) -> io::Result<Vec<u8>> {
+        let content = match self.compression() {
+            Compression::None => self.data,
+            Compression::Zstd => {
+                let mut content = vec![];
+                zstd::stream::Decoder::new(self.data.as_slice())?
+                    .take(limit.saturating_add(1))
+                    .read_to_end(&mut content)?;
+                content
+            }
+        };
+        if content.len() as u64 > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("File content is larger than {limit} bytes"),
+            ));
+        }
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_round_trip() {
+        let content = b"the last yak".repeat(100);
+        let file = File::compress(&content).unwrap();
+        assert!(file.data.len() < content.len());
+        assert_eq!(file.decompress().unwrap(), content);
+    }
+
+    #[test]
+    fn decompression_limit() {
+        let content = vec![0; 1 << 20];
+        let file = File::compress(&content).unwrap();
+        assert!(file.data.len() < 1024);
+        let err = file.clone().decompress_limited(1024).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(file.decompress_limited(1 << 20).unwrap(), content);
+    }
+
+    #[test]
+    fn uncompressed_passthrough() {
+        let file = File {
+            data: b"the last yak".to_vec(),
+            compression: Compression::None.into(),
+        };
+        assert_eq!(file.decompress().unwrap(), b"the last yak");
+    }
+}
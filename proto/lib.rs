@@ -1,5 +1,24 @@
+pub mod address;
+pub mod compression;
+
 pub mod jj_interface {
     tonic::include_proto!("jj_interface");
 }
 
+pub mod control {
+    tonic::include_proto!("control");
+}
+
+pub mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+pub use address::{default_address, pidfile_path, Address};
+
 pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("grpc_descriptor");
+
+/// Environment variable that overrides the daemon address on both sides.
+pub const ADDRESS_ENV: &str = "CULTIVATE_ADDRESS";
+
+/// gRPC metadata key naming the repository a `JujutsuInterface` call is for.
+pub const REPO_ID_METADATA: &str = "cultivate-repo-id";